@@ -0,0 +1,40 @@
+use std::time::Instant;
+
+use sand_3d::{model::{VoxelMaterial, World}, voxel_simulator::VoxelSimulator};
+
+const STEPS: u32 = 20;
+
+fn create_world() -> World {
+    let materials = ["Sand", "Water", "Salt", "Oil", "Steam"].map(|i| VoxelMaterial::from_name(i).unwrap());
+    let mut world = World::new(128, 96, 128);
+    for y in 16..80 {
+        for z in 0..128 {
+            for x in 0..128 {
+                if (x + z) % 4 != 0 {
+                    world.place(materials[(x / 16 + z / 16 + y / 8) % materials.len()], x, y, z);
+                }
+            }
+        }
+    }
+
+    world
+}
+
+fn steps_per_second(parallel: bool) -> f64 {
+    let mut world = create_world();
+    let mut simulator = VoxelSimulator::with_seed(1);
+    simulator.set_parallel(parallel);
+
+    let start = Instant::now();
+    for _ in 0..STEPS {
+        simulator.next_step(&mut world, &mut ());
+    }
+    STEPS as f64 / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let serial = steps_per_second(false);
+    let parallel = steps_per_second(true);
+    println!("serial:   {:.2} steps/s", serial);
+    println!("parallel: {:.2} steps/s on {} threads ({:.2}x)", parallel, rayon::current_num_threads(), parallel / serial);
+}
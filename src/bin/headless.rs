@@ -0,0 +1,159 @@
+use std::{env, fs::File, io::{BufWriter, Write}, path::Path, process, time::Instant};
+
+use sand_3d::{material_reactions::MaterialReactions, material_registry::MaterialRegistry, mesh_export::{self, SurfaceMesh}, model::World, vox_file::{self, VoxMaterialMapping}, voxel_simulator::VoxelSimulator, world_file::{self, SaveMetadata}};
+
+const DEFAULT_OUTPUT_PATH: &str = "output.sand";
+const DEFAULT_COUNTS_PATH: &str = "counts.csv";
+// same scale as the voxels in the GUI scene
+const MESH_VOXEL_SIZE: f32 = 3.0;
+const USAGE: &str = "Usage: sand_3d_headless INPUT --ticks N [--seed SEED] [--materials FILE] [--reactions FILE] [--output FILE] [--counts FILE] [--export-mesh FILE]";
+
+struct Options{
+    input: String,
+    ticks: u64,
+    seed: Option<u64>,
+    materials: Option<String>,
+    reactions: Option<String>,
+    output: String,
+    counts: String,
+    mesh: Option<String>
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn parse_options() -> Options {
+    let mut args = env::args().skip(1);
+    let mut input = None;
+    let mut ticks = None;
+    let mut seed = None;
+    let mut materials = None;
+    let mut reactions = None;
+    let mut output = DEFAULT_OUTPUT_PATH.to_string();
+    let mut counts = DEFAULT_COUNTS_PATH.to_string();
+    let mut mesh = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| exit_with_error(USAGE));
+        match arg.as_str() {
+            "--ticks" => ticks = Some(value().parse().unwrap_or_else(|_| exit_with_error(USAGE))),
+            "--seed" => seed = Some(value().parse().unwrap_or_else(|_| exit_with_error(USAGE))),
+            "--materials" => materials = Some(value()),
+            "--reactions" => reactions = Some(value()),
+            "--output" => output = value(),
+            "--counts" => counts = value(),
+            "--export-mesh" => mesh = Some(value()),
+            _ if input.is_none() && !arg.starts_with("--") => input = Some(arg),
+            _ => exit_with_error(USAGE),
+        }
+    }
+
+    Options {
+        input: input.unwrap_or_else(|| exit_with_error(USAGE)),
+        ticks: ticks.unwrap_or_else(|| exit_with_error(USAGE)),
+        seed,
+        materials,
+        reactions,
+        output,
+        counts,
+        mesh
+    }
+}
+
+fn is_vox(path: &str) -> bool {
+    path.to_lowercase().ends_with(".vox")
+}
+
+fn load(path: &str) -> (World, SaveMetadata) {
+    let loaded = if is_vox(path) {
+        vox_file::import_from_path(Path::new(path), &VoxMaterialMapping::new())
+            .map(|world| (world, SaveMetadata::default()))
+            .map_err(|e| e.to_string())
+    }else{
+        world_file::load_from_path(Path::new(path)).map_err(|e| e.to_string())
+    };
+
+    loaded.unwrap_or_else(|e| exit_with_error(&format!("Failed to load {}: {}", path, e)))
+}
+
+fn save(path: &str, world: &World, metadata: SaveMetadata) {
+    let saved = if is_vox(path) {
+        vox_file::export_to_path(world, Path::new(path)).map_err(|e| e.to_string())
+    }else{
+        world_file::save_to_path(world, metadata, Path::new(path)).map_err(|e| e.to_string())
+    };
+
+    if let Err(e) = saved {
+        exit_with_error(&format!("Failed to save {}: {}", path, e));
+    }
+}
+
+fn export_mesh(path: &str, world: &World) {
+    let mesh = SurfaceMesh::extract(world, MESH_VOXEL_SIZE);
+    mesh_export::export_to_path(&mesh, Path::new(path))
+        .unwrap_or_else(|e| exit_with_error(&format!("Failed to export {}: {}", path, e)));
+    println!("Exported {} triangles to {}", mesh.get_triangle_count(), path);
+}
+
+fn write_counts(writer: &mut impl Write, tick: u64, world: &World) {
+    let counts: Vec<String> = world.get_material_counts()
+        .into_iter()
+        .map(|(_, count)| count.to_string())
+        .collect();
+    writeln!(writer, "{},{}", tick, counts.join(","))
+        .unwrap_or_else(|e| exit_with_error(&format!("Failed to write counts: {}", e)));
+}
+
+fn main() {
+    let options = parse_options();
+    if let Some(path) = &options.materials {
+        MaterialRegistry::load(Path::new(path))
+            .and_then(MaterialRegistry::install)
+            .unwrap_or_else(|e| exit_with_error(&format!("Failed to load materials from {}: {}", path, e)));
+    }
+    let reactions = match &options.reactions {
+        Some(path) => MaterialReactions::load(Path::new(path), MaterialRegistry::get()),
+        None => MaterialReactions::parse_default(MaterialRegistry::get()),
+    };
+    reactions.and_then(MaterialReactions::install)
+        .unwrap_or_else(|e| exit_with_error(&format!("Failed to load reactions: {}", e)));
+    let (mut world, metadata) = load(&options.input);
+    let seed = options.seed.unwrap_or(metadata.seed);
+    let mut voxel_simulator = VoxelSimulator::with_seed(seed);
+    voxel_simulator.set_tick(metadata.tick);
+
+    let counts_file = File::create(&options.counts)
+        .unwrap_or_else(|e| exit_with_error(&format!("Failed to create {}: {}", options.counts, e)));
+    let mut counts_writer = BufWriter::new(counts_file);
+    let header: Vec<String> = world.get_material_counts()
+        .into_iter()
+        .map(|(material, _)| material.to_string())
+        .collect();
+    writeln!(counts_writer, "tick,{}", header.join(","))
+        .unwrap_or_else(|e| exit_with_error(&format!("Failed to write counts: {}", e)));
+    write_counts(&mut counts_writer, voxel_simulator.get_tick(), &world);
+
+    let start_time = Instant::now();
+    for _ in 0..options.ticks {
+        voxel_simulator.next_step(&mut world, &mut ());
+        write_counts(&mut counts_writer, voxel_simulator.get_tick(), &world);
+    }
+    counts_writer.flush()
+        .unwrap_or_else(|e| exit_with_error(&format!("Failed to write counts: {}", e)));
+    let elapsed = start_time.elapsed();
+
+    save(&options.output, &world, SaveMetadata { seed, tick: voxel_simulator.get_tick() });
+    println!(
+        "Simulated {} ticks with seed {} in {}ms ({:.1} ticks/s), wrote {} and {}",
+        options.ticks,
+        seed,
+        elapsed.as_millis(),
+        options.ticks as f64 / elapsed.as_secs_f64().max(1e-9),
+        options.output,
+        options.counts
+    );
+    if let Some(path) = &options.mesh {
+        export_mesh(path, &world);
+    }
+}
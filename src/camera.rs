@@ -12,10 +12,11 @@ pub struct Camera{
     eye_y: f32,
     eye_z: f32,
     at: Point3<f32>,
+    bounds_scale: f32,
     fp_camera: FirstPerson
 }
 impl Camera{
-    pub fn new(eye_x: f32, eye_y: f32, eye_z: f32, at_x: f32, at_y: f32, at_z: f32) -> Self {
+    pub fn new(eye_x: f32, eye_y: f32, eye_z: f32, at_x: f32, at_y: f32, at_z: f32, bounds_scale: f32) -> Self {
         let at = Point3::new(at_x, at_y, at_z);
         let fp_camera = FirstPerson::new(
             Point3::new(eye_x, eye_y, eye_z),
@@ -27,6 +28,7 @@ impl Camera{
             eye_y,
             eye_z,
             at,
+            bounds_scale,
             fp_camera
         }
     }
@@ -40,19 +42,19 @@ impl Camera{
 
     pub fn move_x(&mut self, x: f32) {
         self.eye_x += x;
-        self.eye_x = self.eye_x.max(MIN_EYE_X).min(MAX_EYE_X);
+        self.eye_x = self.eye_x.clamp(MIN_EYE_X * self.bounds_scale, MAX_EYE_X * self.bounds_scale);
         self.update_camera();
     }
 
     pub fn move_y(&mut self, y: f32) {
         self.eye_y += y;
-        self.eye_y = self.eye_y.max(MIN_EYE_Y).min(MAX_EYE_Y);
+        self.eye_y = self.eye_y.clamp(MIN_EYE_Y * self.bounds_scale, MAX_EYE_Y * self.bounds_scale);
         self.update_camera();
     }
 
     pub fn move_z(&mut self, z: f32) {
         self.eye_z += z;
-        self.eye_z = self.eye_z.max(MIN_EYE_Z).min(MAX_EYE_Z);
+        self.eye_z = self.eye_z.clamp(MIN_EYE_Z * self.bounds_scale, MAX_EYE_Z * self.bounds_scale);
         self.update_camera();
     }
 
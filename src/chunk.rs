@@ -0,0 +1,133 @@
+use crate::model::{VoxelMaterial, AMBIENT_TEMPERATURE};
+
+pub const CHUNK_SIZE: usize = 16;
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE*CHUNK_SIZE*CHUNK_SIZE;
+const MOVED_WORDS: usize = CHUNK_VOLUME / 64;
+
+pub struct Chunk{
+    voxels: [VoxelMaterial; CHUNK_VOLUME],
+    temperatures: [f32; CHUNK_VOLUME],
+    velocities: [f32; CHUNK_VOLUME],
+    ages: [u16; CHUNK_VOLUME],
+    moved: [u64; MOVED_WORDS],
+    occupied: usize,
+    scheduled: bool,
+    awake: bool
+}
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Chunk {
+    pub fn new() -> Self {
+        Self {
+            voxels: [VoxelMaterial::AIR; CHUNK_VOLUME],
+            temperatures: [AMBIENT_TEMPERATURE; CHUNK_VOLUME],
+            velocities: [0.0; CHUNK_VOLUME],
+            ages: [0; CHUNK_VOLUME],
+            moved: [0; MOVED_WORDS],
+            occupied: 0,
+            scheduled: true,
+            awake: true
+        }
+    }
+
+    pub fn coordiantes_to_index(x: usize, y: usize, z: usize) -> usize {
+        x + z*CHUNK_SIZE + y*CHUNK_SIZE*CHUNK_SIZE
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> VoxelMaterial {
+        self.voxels[Self::coordiantes_to_index(x, y, z)]
+    }
+
+    pub fn set(&mut self, material: VoxelMaterial, x: usize, y: usize, z: usize) {
+        let index = Self::coordiantes_to_index(x, y, z);
+        let was_air = self.voxels[index] == VoxelMaterial::AIR;
+        let is_air = material == VoxelMaterial::AIR;
+        if was_air && !is_air {
+            self.occupied += 1;
+        }else if !was_air && is_air {
+            self.occupied -= 1;
+        }
+        if self.voxels[index] != material {
+            self.ages[index] = 0;
+        }
+        self.voxels[index] = material;
+    }
+
+    pub fn get_temperature(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.temperatures[Self::coordiantes_to_index(x, y, z)]
+    }
+
+    pub fn set_temperature(&mut self, temperature: f32, x: usize, y: usize, z: usize) {
+        self.temperatures[Self::coordiantes_to_index(x, y, z)] = temperature;
+    }
+
+    pub fn get_velocity(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.velocities[Self::coordiantes_to_index(x, y, z)]
+    }
+
+    pub fn set_velocity(&mut self, velocity: f32, x: usize, y: usize, z: usize) {
+        self.velocities[Self::coordiantes_to_index(x, y, z)] = velocity;
+    }
+
+    pub fn get_age(&self, x: usize, y: usize, z: usize) -> u16 {
+        self.ages[Self::coordiantes_to_index(x, y, z)]
+    }
+
+    pub fn set_age(&mut self, age: u16, x: usize, y: usize, z: usize) {
+        self.ages[Self::coordiantes_to_index(x, y, z)] = age;
+    }
+
+    pub fn is_moved(&self, x: usize, y: usize, z: usize) -> bool {
+        let index = Self::coordiantes_to_index(x, y, z);
+        self.moved[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    pub fn set_moved(&mut self, x: usize, y: usize, z: usize) {
+        let index = Self::coordiantes_to_index(x, y, z);
+        self.moved[index / 64] |= 1 << (index % 64);
+    }
+
+    /// Schedules the chunk for this tick if it was woken during the previous one.
+    pub fn begin_tick(&mut self) {
+        self.moved = [0; MOVED_WORDS];
+        self.scheduled = self.awake;
+        self.awake = false;
+    }
+
+    pub fn is_scheduled(&self) -> bool {
+        self.scheduled
+    }
+
+    pub fn is_awake(&self) -> bool {
+        self.awake
+    }
+
+    pub fn wake(&mut self) {
+        self.awake = true;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.occupied == 0
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::Chunk;
+    use crate::model::VoxelMaterial;
+
+    #[test]
+    fn test_age_resets_on_material_change() {
+        let sand = VoxelMaterial::from_name("Sand").unwrap();
+        let mut chunk = Chunk::new();
+        chunk.set(sand, 1, 2, 3);
+        chunk.set_age(12, 1, 2, 3);
+        chunk.set(sand, 1, 2, 3);
+        assert_eq!(chunk.get_age(1, 2, 3), 12);
+        chunk.set(VoxelMaterial::from_name("Water").unwrap(), 1, 2, 3);
+        assert_eq!(chunk.get_age(1, 2, 3), 0);
+    }
+}
@@ -1,13 +1,16 @@
 use std::{collections::VecDeque, mem::take, rc::Rc};
 
-use kiss3d::{event::{Action, Key, MouseButton}, nalgebra::{Point2, Point3, Translation2}, ncollide3d::bounding_volume, scene::PlanarSceneNode, text::Font, window::Window};
+use kiss3d::{event::{Action, Key}, nalgebra::{Point2, Point3, Translation2}, scene::PlanarSceneNode, text::Font, window::Window};
 
-use crate::{model::{VoxelMaterial, World, WORLD_SIZE}, scene_generator::VOXEL_SIZE, scene_map::SceneMap, wire_cube::WireCube};
+use sand_3d::model::{VoxelMaterial, World};
+
+use crate::{scene_generator::VOXEL_SIZE, scene_map::SceneMap, wire_cube::WireCube};
 
 const FONT_SIZE: f32 = 46.0;
 const SELECTED_BG_WIDTH: f32 = 400.0;
 const SELECTED_BG_HEIGHT: f32 = 50.0;
 const LINES_WIDTH: f32 = 2.0;
+const INFO_FONT_SIZE: f32 = 30.0;
 
 pub struct Cursor{
     x: usize,
@@ -21,20 +24,10 @@ pub struct Cursor{
 }
 impl Cursor{
     pub fn new(x: usize, y: usize, z: usize) -> Self {
-        let materials = VecDeque::from([
-            VoxelMaterial::Sand,
-            VoxelMaterial::Water,
-            VoxelMaterial::Steam,
-            VoxelMaterial::Salt,
-            VoxelMaterial::SaltWater,
-            VoxelMaterial::Lava,
-            VoxelMaterial::Stone,
-            VoxelMaterial::Metal,
-            VoxelMaterial::Wood,
-            VoxelMaterial::Fire,
-            VoxelMaterial::Oil,
-            VoxelMaterial::Ice,  
-        ]);
+        let materials: VecDeque<VoxelMaterial> = VoxelMaterial::get_all()
+            .into_iter()
+            .filter(|i| i.is_placeable())
+            .collect();
         let font = Font::default();
         
         Self { x, y, z, materials, font, selected_bg: None, left_down: false, right_down: false }
@@ -78,15 +71,15 @@ impl Cursor{
     }
 
     pub fn input_move(&mut self, x: f64, y: f64, world: &mut World, scene_map: &mut SceneMap) {
-        self.x = (x * WORLD_SIZE as f64).floor() as usize;
-        self.z = (y * WORLD_SIZE as f64).floor() as usize;
+        self.x = (x * world.get_width() as f64).floor() as usize;
+        self.z = (y * world.get_depth() as f64).floor() as usize;
         self.update(world, scene_map);
     }
 
     pub fn input_scroll(&mut self, amount: f64, world: &mut World, scene_map: &mut SceneMap) {
         if amount < 0.0 && self.y > 0{
             self.y -= 1;
-        }else if amount > 0.0 && self.y + 1 < WORLD_SIZE {
+        }else if amount > 0.0 && self.y + 1 < world.get_height() {
             self.y += 1;
         }else{
             return;
@@ -95,13 +88,13 @@ impl Cursor{
     }
     
     pub fn update(&self, world: &mut World, scene_map: &mut SceneMap) {
-        if self.x >= WORLD_SIZE || self.y >= WORLD_SIZE || self.z >= WORLD_SIZE {
+        if !world.is_in_bounds(self.x, self.y, self.z) {
             return;
         }
         if self.left_down {
-            world.set(*self.materials.front().unwrap(), self.x, self.y, self.z);    
+            world.place(*self.materials.front().unwrap(), self.x, self.y, self.z);    
         }else if self.right_down {
-            world.set(VoxelMaterial::Air, self.x, self.y, self.z);       
+            world.set(VoxelMaterial::AIR, self.x, self.y, self.z);       
         }else{
             return;
         }
@@ -114,6 +107,8 @@ impl Cursor{
             offset_y + self.y as f32 * VOXEL_SIZE - VOXEL_SIZE/2.0,
             offset_z + self.z as f32 * VOXEL_SIZE - VOXEL_SIZE/2.0,
             VOXEL_SIZE,
+            VOXEL_SIZE,
+            VOXEL_SIZE,
             1.0, 0.1, 0.1
         );
 
@@ -155,6 +150,21 @@ impl Cursor{
         );
     }
 
+    pub fn draw_simulation_info(&self, window: &mut Window, world: &World, seed: u64, tick: u64) {
+        let temperature = if world.is_in_bounds(self.x, self.y, self.z) {
+            world.get_temperature(self.x, self.y, self.z)
+        }else{
+            0.0
+        };
+        window.draw_text(
+            &format!("Seed:{} Tick:{} Temperature:{:.0}", seed, tick, temperature),
+            &Point2::new(0.0, SELECTED_BG_HEIGHT + LINES_WIDTH*2.0),
+            INFO_FONT_SIZE,
+            &self.font,
+            &Point3::new(0.0, 0.0, 0.0)
+        );
+    }
+
     pub fn delete_nodes(&mut self, window: &mut Window) {
         if let Some(mut bg) =  take(&mut self.selected_bg) {
             window.remove_planar_node(&mut bg);
@@ -0,0 +1,245 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{chunk::{Chunk, CHUNK_SIZE, CHUNK_VOLUME}, material_properties::MaterialProperties, model::{VoxelMaterial, World, AMBIENT_TEMPERATURE}, voxel_simulator::ChangeListener};
+
+const DIFFUSION_RATE: f32 = 1.0 / 24.0;
+const AIR_COOLING_RATE: f32 = 0.01;
+// a chunk is simulated until no temperature in it changes by more than this
+const WAKE_TEMPERATURE_CHANGE: f32 = 0.01;
+
+type Position = (usize, usize, usize);
+
+/// Exchanges heat in the chunks that are being simulated. Chunks around them receive heat across the shared
+/// faces and are woken by it, sleeping chunks are left alone.
+pub struct HeatSimulator{
+    face_neighbours: [(isize, isize, isize); 6],
+    visited_voxels: usize
+}
+impl Default for HeatSimulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl HeatSimulator {
+    pub fn new() -> Self {
+        Self { face_neighbours: [(1, 0, 0), (0, 1, 0), (0, 0, 1), (-1, 0, 0), (0, -1, 0), (0, 0, -1)], visited_voxels: 0 }
+    }
+
+    /// Voxels whose temperature was updated in the last step.
+    pub fn get_visited_voxel_count(&self) -> usize {
+        self.visited_voxels
+    }
+
+    fn exchange(&self, first: (VoxelMaterial, f32), second: (VoxelMaterial, f32)) -> (f32, f32) {
+        let first_properties = MaterialProperties::new(&first.0);
+        let second_properties = MaterialProperties::new(&second.0);
+        let conductivity = first_properties.conductivity.min(second_properties.conductivity);
+        let heat = (second.1 - first.1) * conductivity * DIFFUSION_RATE;
+
+        (heat / first_properties.heat_capacity, -heat / second_properties.heat_capacity)
+    }
+
+    fn add_delta(
+        deltas: &mut [[f32; CHUNK_VOLUME]],
+        chunk_positions: &HashMap<Position, usize>,
+        delta: f32,
+        x: usize, y: usize, z: usize) {
+        let position = chunk_positions[&(x / CHUNK_SIZE, y / CHUNK_SIZE, z / CHUNK_SIZE)];
+        deltas[position][Chunk::coordiantes_to_index(x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE)] += delta;
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn accumulate_exchanges(
+        &self,
+        world: &World,
+        deltas: &mut [[f32; CHUNK_VOLUME]],
+        chunk_positions: &HashMap<Position, usize>,
+        simulated: &HashSet<Position>,
+        x: usize, y: usize, z: usize) {
+        let current = (world.get(x, y, z), world.get_temperature(x, y, z));
+        for (dx, dy, dz) in self.face_neighbours {
+            let (Some(other_x), Some(other_y), Some(other_z)) = (x.checked_add_signed(dx), y.checked_add_signed(dy), z.checked_add_signed(dz)) else {
+                continue;
+            };
+            if !world.is_in_bounds(other_x, other_y, other_z) {
+                continue;
+            }
+            // pairs inside the simulated chunks are seen from both sides and exchange once, unallocated chunks
+            // are ambient air that only takes heat
+            let other_chunk = (other_x / CHUNK_SIZE, other_y / CHUNK_SIZE, other_z / CHUNK_SIZE);
+            if dx + dy + dz < 0 && simulated.contains(&other_chunk) {
+                continue;
+            }
+            let other = (world.get(other_x, other_y, other_z), world.get_temperature(other_x, other_y, other_z));
+            let (delta, other_delta) = self.exchange(current, other);
+            Self::add_delta(deltas, chunk_positions, delta, x, y, z);
+            if chunk_positions.contains_key(&other_chunk) {
+                Self::add_delta(deltas, chunk_positions, other_delta, other_x, other_y, other_z);
+            }
+        }
+    }
+
+    pub fn next_step(&mut self, world: &mut World, listener: &mut dyn ChangeListener) {
+        // chunks simulated in the last tick and the ones woken since
+        let mut simulated = world.get_scheduled_chunks();
+        simulated.extend(world.get_active_chunks());
+        let chunks = world.get_allocated_chunks_around(&simulated);
+        let simulated: HashSet<Position> = simulated.into_iter().collect();
+        let chunk_positions: HashMap<Position, usize> = chunks.iter()
+            .enumerate()
+            .map(|(i, chunk)| (*chunk, i))
+            .collect();
+        let mut deltas = vec![[0.0; CHUNK_VOLUME]; chunks.len()];
+        self.visited_voxels = 0;
+
+        for (chunk_x, chunk_y, chunk_z) in chunks.iter().filter(|i| simulated.contains(i)) {
+            let ((min_x, max_x), (min_y, max_y), (min_z, max_z)) = world.get_chunk_bounds(*chunk_x, *chunk_y, *chunk_z);
+            for y in min_y..max_y {
+                for z in min_z..max_z {
+                    for x in min_x..max_x {
+                        self.accumulate_exchanges(world, &mut deltas, &chunk_positions, &simulated, x, y, z);
+                    }
+                }
+            }
+        }
+
+        for (position, (chunk_x, chunk_y, chunk_z)) in chunks.into_iter().enumerate() {
+            let ((min_x, max_x), (min_y, max_y), (min_z, max_z)) = world.get_chunk_bounds(chunk_x, chunk_y, chunk_z);
+            for y in min_y..max_y {
+                for z in min_z..max_z {
+                    for x in min_x..max_x {
+                        self.visited_voxels += 1;
+                        let material = world.get(x, y, z);
+                        let properties = MaterialProperties::new(&material);
+                        let delta = deltas[position][Chunk::coordiantes_to_index(x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE)];
+                        let previous = world.get_temperature(x, y, z);
+                        let mut temperature = previous + delta;
+                        if material == VoxelMaterial::AIR {
+                            temperature += (AMBIENT_TEMPERATURE - temperature) * AIR_COOLING_RATE;
+                        }
+                        if temperature < properties.initial_temperature {
+                            temperature = (temperature + properties.heat_generation).min(properties.initial_temperature);
+                        }
+                        world.set_temperature(temperature, x, y, z);
+                        if (temperature - previous).abs() > WAKE_TEMPERATURE_CHANGE {
+                            world.wake(x, y, z);
+                        }
+                        if let Some(product) = properties.get_transition(temperature) {
+                            world.set(product, x, y, z);
+                            listener.voxel_changed(x, y, z);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::HeatSimulator;
+    use crate::model::{VoxelMaterial, World, AMBIENT_TEMPERATURE};
+
+    #[test]
+    fn test_heat_flows_from_hot_to_cold() {
+        let mut world = World::new(20, 1, 1);
+        for x in 0..20 {
+            world.set(VoxelMaterial::from_name("Metal").unwrap(), x, 0, 0);
+        }
+        world.set_temperature(1000.0, 0, 0, 0);
+        let mut simulator = HeatSimulator::new();
+
+        for _ in 0..200 {
+            simulator.next_step(&mut world, &mut ());
+        }
+
+        let temperatures: Vec<f32> = (0..20).map(|x| world.get_temperature(x, 0, 0)).collect();
+        assert!(temperatures.windows(2).all(|i| i[0] >= i[1]));
+        assert!(temperatures[19] > AMBIENT_TEMPERATURE);
+        let total: f32 = temperatures.iter().sum();
+        assert!((total - (1000.0 + 19.0*AMBIENT_TEMPERATURE)).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_conductivity_matters() {
+        let mut world = World::new(2, 2, 1);
+        world.set(VoxelMaterial::from_name("Metal").unwrap(), 0, 0, 0);
+        world.set(VoxelMaterial::from_name("Metal").unwrap(), 1, 0, 0);
+        world.set(VoxelMaterial::from_name("Wood").unwrap(), 0, 1, 0);
+        world.set(VoxelMaterial::from_name("Wood").unwrap(), 1, 1, 0);
+        world.set_temperature(500.0, 0, 0, 0);
+        world.set_temperature(500.0, 0, 1, 0);
+        let mut simulator = HeatSimulator::new();
+
+        for _ in 0..10 {
+            simulator.next_step(&mut world, &mut ());
+        }
+
+        assert!(world.get_temperature(1, 0, 0) > world.get_temperature(1, 1, 0));
+    }
+
+    #[test]
+    fn test_heat_sources() {
+        let mut world = World::new(5, 5, 5);
+        world.set(VoxelMaterial::from_name("Fire").unwrap(), 2, 2, 2);
+        world.set(VoxelMaterial::from_name("Ice").unwrap(), 2, 3, 2);
+        world.set_temperature(-10.0, 2, 3, 2);
+        let mut simulator = HeatSimulator::new();
+
+        for _ in 0..100 {
+            simulator.next_step(&mut world, &mut ());
+        }
+
+        assert!(world.get_temperature(2, 2, 2) > 700.0);
+        assert!(world.get_temperature(2, 3, 2) > 0.0);
+        assert!(world.get_temperature(0, 0, 0) < world.get_temperature(2, 1, 2));
+    }
+
+    #[test]
+    fn test_melting_and_boiling() {
+        let mut world = World::new(3, 1, 1);
+        world.place(VoxelMaterial::from_name("Ice").unwrap(), 0, 0, 0);
+        world.set_temperature(0.5, 0, 0, 0);
+        world.place(VoxelMaterial::from_name("Water").unwrap(), 1, 0, 0);
+        world.set_temperature(101.0, 1, 0, 0);
+        world.place(VoxelMaterial::from_name("Steam").unwrap(), 2, 0, 0);
+        world.set_temperature(50.0, 2, 0, 0);
+
+        HeatSimulator::new().next_step(&mut world, &mut ());
+
+        assert_eq!(world.get(0, 0, 0), VoxelMaterial::from_name("Water").unwrap());
+        assert_eq!(world.get(1, 0, 0), VoxelMaterial::from_name("Steam").unwrap());
+        assert_eq!(world.get(2, 0, 0), VoxelMaterial::from_name("Water").unwrap());
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut world = World::new(4, 1, 1);
+        world.place(VoxelMaterial::from_name("Water").unwrap(), 0, 0, 0);
+        world.set_temperature(98.0, 0, 0, 0);
+        world.place(VoxelMaterial::from_name("Steam").unwrap(), 3, 0, 0);
+        world.set_temperature(98.0, 3, 0, 0);
+
+        HeatSimulator::new().next_step(&mut world, &mut ());
+
+        assert_eq!(world.get(0, 0, 0), VoxelMaterial::from_name("Water").unwrap());
+        assert_eq!(world.get(3, 0, 0), VoxelMaterial::from_name("Steam").unwrap());
+    }
+
+    #[test]
+    fn test_metal_melts_next_to_lava() {
+        let mut world = World::new(3, 3, 3);
+        for (x, y, z) in [(0, 1, 1), (2, 1, 1), (1, 0, 1), (1, 2, 1), (1, 1, 0), (1, 1, 2)] {
+            world.place(VoxelMaterial::from_name("Lava").unwrap(), x, y, z);
+        }
+        world.place(VoxelMaterial::from_name("Metal").unwrap(), 1, 1, 1);
+        world.set_temperature(990.0, 1, 1, 1);
+        let mut simulator = HeatSimulator::new();
+
+        for _ in 0..50 {
+            simulator.next_step(&mut world, &mut ());
+        }
+
+        assert_eq!(world.get(1, 1, 1), VoxelMaterial::from_name("Molten Metal").unwrap());
+    }
+}
@@ -0,0 +1,11 @@
+pub mod model;
+pub mod chunk;
+pub mod material_properties;
+pub mod material_registry;
+pub mod material_reactions;
+pub mod voxel_simulator;
+pub mod heat_simulator;
+pub mod structure_simulator;
+pub mod world_file;
+pub mod vox_file;
+pub mod mesh_export;
\ No newline at end of file
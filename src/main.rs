@@ -1,16 +1,14 @@
 extern crate kiss3d;
 
-mod model;
 mod scene_generator;
 mod texture_generator;
 mod wire_cube;
-mod material_properties;
-mod voxel_simulator;
-mod material_reactions;
 mod scene_map;
 mod cursor;
 mod camera;
 
+use std::env;
+use std::path::Path;
 use std::time::Instant;
 
 use camera::Camera;
@@ -19,31 +17,167 @@ use kiss3d::event::{Action, Key};
 use kiss3d::event::WindowEvent;
 use kiss3d::window::Window;
 use kiss3d::light::Light;
-use model::{World, WORLD_SIZE};
+use sand_3d::material_reactions::MaterialReactions;
+use sand_3d::material_registry::MaterialRegistry;
+use sand_3d::model::{World, MAX_WORLD_SIZE};
+use sand_3d::vox_file::{self, VoxMaterialMapping};
+use sand_3d::voxel_simulator::VoxelSimulator;
+use sand_3d::world_file::{self, SaveMetadata};
 use scene_generator::{SceneGenerator, VOXEL_SIZE};
 use scene_map::SceneMap;
-use voxel_simulator::VoxelSimulator;
 
 const FPS: u64 = 60;
-const ORIGIN_X: f32 = -VOXEL_SIZE * WORLD_SIZE as f32 / 2.0;
-const ORIGIN_Y: f32 = -VOXEL_SIZE * WORLD_SIZE as f32 * 1.5;
+const DEFAULT_WORLD_SIZE: usize = 30;
 const ORIGIN_Z: f32 = 85.0;
 const TIME_BETWEEN_STEPS_US: u128 = 50_000;
 
 const CAMERA_MOVEMENT_SPEED: f32 = 5e-5;
+const QUICK_SAVE_PATH: &str = "quicksave.sand";
+const VOX_EXPORT_PATH: &str = "export.vox";
+const USAGE: &str = "Usage: sand_3d [--seed SEED] [--materials FILE] [--reactions FILE] [WIDTH HEIGHT DEPTH | --load FILE | --import-vox FILE [MAPPING]]";
 
-fn print_time(render_time: u128, generation_time: u128, simulation_time: u128) {
+fn print_time(render_time: u128, generation_time: u128, simulation_time: u128, allocated_chunks: usize, active_chunks: usize) {
     let total_time = render_time + generation_time + simulation_time;
     println!(
-        "Render time: {}ms {}us; Mesh generation time: {}ms {}us; Simulation time: {}ms {}us; Total: {}ms {}us",
+        "Render time: {}ms {}us; Mesh generation time: {}ms {}us; Simulation time: {}ms {}us; Total: {}ms {}us; Chunks: {} ({} active)",
         render_time/1000, render_time%1000,
         generation_time/1000, generation_time%1000,
         simulation_time/1000, simulation_time%1000,
-        total_time/1000, total_time%1000
+        total_time/1000, total_time%1000,
+        allocated_chunks, active_chunks
     );
 }
 
+fn exit_with_usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(1);
+}
+
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let position = args.iter().position(|i| i == name)?;
+    if position + 1 >= args.len() {
+        exit_with_usage();
+    }
+    let value = args.remove(position + 1);
+    args.remove(position);
+
+    Some(value)
+}
+
+fn load_materials(path: &str) {
+    let loaded = MaterialRegistry::load(Path::new(path)).and_then(MaterialRegistry::install);
+    if let Err(e) = loaded {
+        eprintln!("Failed to load materials from {}: {}", path, e);
+        std::process::exit(1);
+    }
+}
+
+fn load_reactions(path: Option<&str>) {
+    let loaded = match path {
+        Some(path) => MaterialReactions::load(Path::new(path), MaterialRegistry::get()),
+        None => MaterialReactions::parse_default(MaterialRegistry::get()),
+    };
+    if let Err(e) = loaded.and_then(MaterialReactions::install) {
+        eprintln!("Failed to load reactions from {}: {}", path.unwrap_or("the built-in table"), e);
+        std::process::exit(1);
+    }
+}
+
+fn create_world(args: &[String]) -> (World, Option<SaveMetadata>) {
+    if args.is_empty() {
+        return (World::new(DEFAULT_WORLD_SIZE, DEFAULT_WORLD_SIZE, DEFAULT_WORLD_SIZE), None);
+    }
+    if args[0] == "--load" {
+        if args.len() != 2 {
+            exit_with_usage();
+        }
+        let (world, metadata) = world_file::load_from_path(Path::new(&args[1])).unwrap_or_else(|e| {
+            eprintln!("Failed to load {}: {}", args[1], e);
+            std::process::exit(1);
+        });
+        return (world, Some(metadata));
+    }
+    if args[0] == "--import-vox" {
+        if args.len() != 2 && args.len() != 3 {
+            exit_with_usage();
+        }
+        let mapping = match args.get(2) {
+            Some(path) => VoxMaterialMapping::load(Path::new(path)).unwrap_or_else(|e| {
+                eprintln!("Failed to load mapping {}: {}", path, e);
+                std::process::exit(1);
+            }),
+            None => VoxMaterialMapping::new(),
+        };
+        let world = vox_file::import_from_path(Path::new(&args[1]), &mapping).unwrap_or_else(|e| {
+            eprintln!("Failed to import {}: {}", args[1], e);
+            std::process::exit(1);
+        });
+        return (world, None);
+    }
+    let dimensions: Vec<usize> = args.iter()
+        .map(|i| i.parse().ok().filter(|size| *size > 0 && *size <= MAX_WORLD_SIZE))
+        .collect::<Option<Vec<usize>>>()
+        .filter(|dimensions| dimensions.len() == 3)
+        .unwrap_or_else(|| exit_with_usage());
+
+    (World::new(dimensions[0], dimensions[1], dimensions[2]), None)
+}
+
+fn quick_save(world: &World, voxel_simulator: &VoxelSimulator) {
+    let metadata = SaveMetadata { seed: voxel_simulator.get_seed(), tick: voxel_simulator.get_tick() };
+    match world_file::save_to_path(world, metadata, Path::new(QUICK_SAVE_PATH)) {
+        Ok(()) => println!("Saved world to {}", QUICK_SAVE_PATH),
+        Err(e) => eprintln!("Failed to save world: {}", e),
+    }
+}
+
+fn export_vox(world: &World) {
+    match vox_file::export_to_path(world, Path::new(VOX_EXPORT_PATH)) {
+        Ok(()) => println!("Exported world to {}", VOX_EXPORT_PATH),
+        Err(e) => eprintln!("Failed to export world: {}", e),
+    }
+}
+
+fn quick_load(world: &mut World, voxel_simulator: &mut VoxelSimulator, scene_map: &mut SceneMap, window: &mut Window) {
+    let (loaded, metadata) = match world_file::load_from_path(Path::new(QUICK_SAVE_PATH)) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("Failed to load world: {}", e);
+            return;
+        },
+    };
+    if loaded.get_width() != world.get_width() || loaded.get_height() != world.get_height() || loaded.get_depth() != world.get_depth() {
+        eprintln!(
+            "Quick save is {}x{}x{}, restart with --load {} to open it",
+            loaded.get_width(), loaded.get_height(), loaded.get_depth(), QUICK_SAVE_PATH
+        );
+        return;
+    }
+
+    scene_map.clear(window);
+    *world = loaded;
+    *scene_map = SceneMap::new(world);
+    voxel_simulator.set_seed(metadata.seed);
+    voxel_simulator.set_tick(metadata.tick);
+    println!("Loaded world from {}", QUICK_SAVE_PATH);
+}
+
 fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let seed = take_option(&mut args, "--seed")
+        .map(|i| i.parse().unwrap_or_else(|_| exit_with_usage()));
+    if let Some(path) = take_option(&mut args, "--materials") {
+        load_materials(&path);
+    }
+    load_reactions(take_option(&mut args, "--reactions").as_deref());
+
+    let (mut world, metadata) = create_world(&args);
+    let (width, height, depth) = (world.get_width(), world.get_height(), world.get_depth());
+    let view_scale = width.max(height).max(depth) as f32 / DEFAULT_WORLD_SIZE as f32;
+    let origin_x = -VOXEL_SIZE * width as f32 / 2.0;
+    let origin_y = -VOXEL_SIZE * height as f32 * 1.5;
+    let origin_z = ORIGIN_Z * view_scale;
+
     let mut window = Window::new("Sand 3D");
     window.set_framerate_limit(Some(FPS));
     window.set_background_color(0.8, 0.8, 0.9);
@@ -53,21 +187,30 @@ fn main() {
         0.0,
         0.0,
         0.0,
-        ORIGIN_X+WORLD_SIZE as f32 * VOXEL_SIZE/2.0,
-        ORIGIN_Y+WORLD_SIZE as f32 * VOXEL_SIZE/3.0,
-        ORIGIN_Z+WORLD_SIZE as f32 * VOXEL_SIZE/2.0
+        origin_x+width as f32 * VOXEL_SIZE/2.0,
+        origin_y+height as f32 * VOXEL_SIZE/3.0,
+        origin_z+depth as f32 * VOXEL_SIZE/2.0,
+        view_scale
     );
     
     window.set_light(Light::StickToCamera);
 
-    let mut world = World::new();
-    let mut voxel_simulator = VoxelSimulator::new();
-    let scene_generator = SceneGenerator::new(ORIGIN_X, ORIGIN_Y, ORIGIN_Z);
+    let mut voxel_simulator = match (seed, metadata) {
+        (Some(seed), _) => VoxelSimulator::with_seed(seed),
+        (None, Some(metadata)) => VoxelSimulator::with_seed(metadata.seed),
+        (None, None) => VoxelSimulator::new(),
+    };
+    if let Some(metadata) = metadata {
+        voxel_simulator.set_tick(metadata.tick);
+    }
+    println!("Simulation seed: {}", voxel_simulator.get_seed());
+    let scene_generator = SceneGenerator::new(origin_x, origin_y, origin_z, &world);
     let mut paused = false;
-    let mut scene_map = SceneMap::new();
-    let mut cursor = Cursor::new(WORLD_SIZE-1, WORLD_SIZE/2, WORLD_SIZE-1);
+    let mut show_active_chunks = false;
+    let mut scene_map = SceneMap::new(&world);
+    let mut cursor = Cursor::new(width-1, height/2, depth-1);
     let mut render_time;
-    let mut generation_time = 0;
+    let mut generation_time;
     let mut simulation_time = 0;
     let mut render_start_time = Instant::now();
     let mut generation_start_time;
@@ -103,6 +246,18 @@ fn main() {
                     if matches!(key, Key::Space) && matches!(action, Action::Press) {
                         paused = !paused;
                     }
+                    if matches!(key, Key::F3) && matches!(action, Action::Press) {
+                        show_active_chunks = !show_active_chunks;
+                    }
+                    if matches!(key, Key::F5) && matches!(action, Action::Press) {
+                        quick_save(&world, &voxel_simulator);
+                    }
+                    if matches!(key, Key::F6) && matches!(action, Action::Press) {
+                        export_vox(&world);
+                    }
+                    if matches!(key, Key::F9) && matches!(action, Action::Press) {
+                        quick_load(&mut world, &mut voxel_simulator, &mut scene_map, &mut window);
+                    }
                     if matches!(key, Key::Escape) {
                         window.close();
                     }
@@ -149,19 +304,22 @@ fn main() {
         }
 
         scene_generator.draw_border(&mut window);
+        if show_active_chunks {
+            scene_generator.draw_active_chunks(&mut window, &world);
+        }
 
         generation_start_time = Instant::now();
         let changed = scene_map.get_and_remove_changed(&mut window);
         scene_generator.generate_scene(&mut window, &world, &mut scene_map, changed);    
         generation_time = generation_start_time.elapsed().as_micros();
 
-        print_time(render_time, generation_time, simulation_time);
+        print_time(render_time, generation_time, simulation_time, world.get_allocated_chunk_count(), world.get_active_chunks().len());
         
-        cursor.draw(&mut window, ORIGIN_X, ORIGIN_Y, ORIGIN_Z);
+        cursor.draw(&mut window, origin_x, origin_y, origin_z);
         cursor.draw_selected(&mut window);
+        cursor.draw_simulation_info(&mut window, &world, voxel_simulator.get_seed(), voxel_simulator.get_tick());
 
         simulation_time = 0;
-        generation_time = 0;
         render_start_time = Instant::now();
     }
 
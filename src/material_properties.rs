@@ -1,30 +1,109 @@
-use crate::model::VoxelMaterial;
+use serde::Deserialize;
 
+use crate::{material_registry::MaterialRegistry, model::VoxelMaterial};
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, Deserialize)]
 pub enum MaterialType {
     LIQUID, POWDER, SOLID, GAS
 }
 
+#[derive(Clone, Copy, Debug)]
+pub struct PhaseTransition{
+    pub temperature: f32,
+    pub product: VoxelMaterial
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Lifetime{
+    pub min: u16,
+    pub max: u16,
+    pub on_expire: VoxelMaterial
+}
+impl Lifetime {
+    /// Chance to expire at the given age, so lifetimes end evenly spread between min and max.
+    pub fn get_expire_chance(&self, age: u16) -> f64 {
+        if age < self.min {
+            0.0
+        }else if age >= self.max {
+            1.0
+        }else{
+            1.0 / (self.max - age + 1) as f64
+        }
+    }
+}
+
+/// How a flammable material burns. A voxel ignites next to anything at its ignition temperature and then burns in
+/// place for `burn_duration` ticks at `burn_temperature`, giving off flames, before it turns into its residue.
+#[derive(Clone, Copy, Debug)]
+pub struct Combustion{
+    pub flammability: f32,
+    pub ignition_temperature: f32,
+    pub burn_duration: u16,
+    pub burn_temperature: f32,
+    pub flame: VoxelMaterial,
+    pub residue: VoxelMaterial
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct MaterialProperties{
     pub material_type: MaterialType,
     pub weight: u32,
-    pub activity: f32
+    pub activity: f32,
+    pub flow_rate: u32,
+    pub viscosity: f32,
+    pub repose_angle: f32,
+    pub wet_repose_angle: f32,
+    pub strength: u32,
+    pub rubble: Option<VoxelMaterial>,
+    pub conductivity: f32,
+    pub heat_capacity: f32,
+    pub initial_temperature: f32,
+    pub heat_generation: f32,
+    pub heating_transition: Option<PhaseTransition>,
+    pub cooling_transition: Option<PhaseTransition>,
+    pub lifetime: Option<Lifetime>,
+    pub combustion: Option<Combustion>
 }
 impl MaterialProperties {
     pub fn new(material: &VoxelMaterial) -> Self {
-        match material {
-            VoxelMaterial::Air => {panic!("Air has no properties")},
-            VoxelMaterial::Sand => MaterialProperties { material_type: MaterialType::POWDER, weight: 10, activity: 1.0 },
-            VoxelMaterial::Water => MaterialProperties { material_type: MaterialType::LIQUID, weight: 5, activity: 1.0 },
-            VoxelMaterial::Metal => MaterialProperties { material_type: MaterialType::SOLID, weight: 100, activity: 0.0 },
-            VoxelMaterial::Salt => MaterialProperties { material_type: MaterialType::POWDER, weight: 10, activity: 1.0 },
-            VoxelMaterial::SaltWater => MaterialProperties { material_type: MaterialType::LIQUID, weight: 6, activity: 1.0 },
-            VoxelMaterial::Steam => MaterialProperties { material_type:MaterialType::GAS, weight: 1, activity: 0.9 },
-            VoxelMaterial::Lava => MaterialProperties { material_type: MaterialType::LIQUID, weight: 9, activity: 0.2 },
-            VoxelMaterial::Stone => MaterialProperties { material_type:MaterialType::POWDER, weight: 10, activity: 0.05 },
-            VoxelMaterial::Wood => MaterialProperties { material_type: MaterialType::SOLID, weight: 100, activity: 0.0 },
-            VoxelMaterial::Fire => MaterialProperties { material_type: MaterialType::GAS, weight: 1, activity: 0.3 },
-            VoxelMaterial::Oil => MaterialProperties { material_type: MaterialType::LIQUID, weight: 4, activity: 1.0 },
-            VoxelMaterial::Ice => MaterialProperties { material_type: MaterialType::SOLID, weight: 100, activity: 0.0 },
+        MaterialRegistry::get().get_entry(*material).properties
+    }
+
+    /// How far a liquid spreads when it does, viscous liquids only get part of their flow rate.
+    pub fn get_flow_distance(&self) -> u32 {
+        ((self.flow_rate as f32 * (1.0 - self.viscosity)).ceil() as u32).max(1)
+    }
+
+    /// Sideways and downward distance of one slide of a powder grain, so piles settle at roughly their angle of
+    /// repose. Wet powders, touching a liquid, use their wet angle.
+    pub fn get_repose_step(&self, wet: bool) -> (usize, usize) {
+        let angle = if wet { self.wet_repose_angle } else { self.repose_angle };
+        let slope = angle.to_radians().tan();
+        if slope >= 1.0 {
+            (1, slope.round() as usize)
+        }else{
+            ((1.0 / slope).round() as usize, 1)
         }
     }
-}
\ No newline at end of file
+
+    /// Chance per tick to sink into a liquid, the closer the weights the more the liquid holds it back.
+    pub fn get_sink_chance(&self, liquid: &MaterialProperties) -> f64 {
+        (1.0 - liquid.weight as f64 / self.weight as f64).max(0.0)
+    }
+
+    pub fn get_transition(&self, temperature: f32) -> Option<VoxelMaterial> {
+        if let Some(transition) = &self.heating_transition {
+            if temperature > transition.temperature {
+                return Some(transition.product);
+            }
+        }
+        if let Some(transition) = &self.cooling_transition {
+            if temperature < transition.temperature {
+                return Some(transition.product);
+            }
+        }
+
+        None
+    }
+}
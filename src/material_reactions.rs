@@ -1,110 +1,248 @@
-use crate::model::VoxelMaterial;
+use std::{fmt::Display, fs, io, path::Path, sync::OnceLock};
 
+use serde::Deserialize;
 
-pub struct MaterialReactions{
-    pub other_material: VoxelMaterial,
+use crate::{material_registry::MaterialRegistry, model::VoxelMaterial};
+
+const DEFAULT_REACTIONS: &str = include_str!("../reactions.ron");
+
+static REACTIONS: OnceLock<MaterialReactions> = OnceLock::new();
+
+#[derive(Debug)]
+pub enum ReactionError {
+    Io(io::Error),
+    Parse(String),
+    Invalid(Vec<String>),
+    AlreadyInstalled,
+}
+impl Display for ReactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Parse(message) => write!(f, "Invalid reactions file: {}", message),
+            Self::Invalid(problems) => write!(f, "Invalid reactions: {}", problems.join("; ")),
+            Self::AlreadyInstalled => write!(f, "Reactions are already in use"),
+        }
+    }
+}
+impl From<io::Error> for ReactionError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+fn default_min_count() -> u32 {
+    1
+}
+
+#[derive(Deserialize)]
+struct ConditionsDefinition{
+    #[serde(default)]
+    min_temperature: Option<f32>,
+    #[serde(default)]
+    max_temperature: Option<f32>,
+    #[serde(default)]
+    catalyst: Option<String>,
+    #[serde(default = "default_min_count")]
+    min_count: u32
+}
+impl Default for ConditionsDefinition {
+    fn default() -> Self {
+        Self { min_temperature: None, max_temperature: None, catalyst: None, min_count: default_min_count() }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ReactionConditions{
+    pub min_temperature: Option<f32>,
+    pub max_temperature: Option<f32>,
+    pub catalyst: Option<VoxelMaterial>,
+    pub min_count: u32
+}
+impl ReactionConditions {
+    pub fn is_met(&self, temperature: f32) -> bool {
+        self.min_temperature.is_none_or(|i| temperature >= i) &&
+        self.max_temperature.is_none_or(|i| temperature <= i)
+    }
+}
+
+#[derive(Deserialize)]
+struct ReactionDefinition{
+    reactant: String,
+    other: String,
+    #[serde(default)]
+    third: Option<String>,
+    products: Vec<String>,
+    probability: f32,
+    #[serde(default)]
+    conditions: ConditionsDefinition,
+    #[serde(default)]
+    spawn: Option<String>
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Reaction{
+    pub third: Option<VoxelMaterial>,
     pub first_product: VoxelMaterial,
     pub second_product: VoxelMaterial,
-    pub probability: f32
+    pub third_product: VoxelMaterial,
+    pub probability: f32,
+    pub conditions: ReactionConditions,
+    pub spawn: Option<VoxelMaterial>
+}
+
+pub struct MaterialReactions{
+    material_count: usize,
+    table: Vec<Option<Reaction>>,
+    reactive: Vec<bool>
 }
 impl MaterialReactions {
-    pub fn new(material: &VoxelMaterial) -> Vec<MaterialReactions> {
-        match material {
-            VoxelMaterial::Salt => vec![
-                MaterialReactions{
-                    other_material: VoxelMaterial::Water,
-                    first_product: VoxelMaterial::Air,
-                    second_product: VoxelMaterial::SaltWater,
-                    probability: 0.1
+    pub fn parse(source: &str, materials: &MaterialRegistry) -> Result<Self, ReactionError> {
+        let definitions: Vec<ReactionDefinition> = ron::from_str(source)
+            .map_err(|e| ReactionError::Parse(e.to_string()))?;
+
+        let material_count = materials.get_material_count();
+        let mut table = vec![None; material_count*material_count];
+        let mut reactive = vec![false; material_count];
+        let mut problems = vec![];
+        for (number, definition) in definitions.iter().enumerate() {
+            let description = format!("reaction {} ({} + {})", number + 1, definition.reactant, definition.other);
+            let mut find = |name: &str| {
+                let material = materials.find(name);
+                if material.is_none() {
+                    problems.push(format!("{} uses unknown material '{}'", description, name));
                 }
-            ],
-            VoxelMaterial::Steam => vec![
-                MaterialReactions{
-                    other_material: VoxelMaterial::Air,
-                    first_product: VoxelMaterial::Water,
-                    second_product: VoxelMaterial::Air,
-                    probability: 0.01
+                material
+            };
+            let reactant = find(&definition.reactant);
+            let other = find(&definition.other);
+            let third = definition.third.as_deref().map(&mut find);
+            let products: Vec<Option<VoxelMaterial>> = definition.products.iter().map(|i| find(i)).collect();
+            let catalyst = definition.conditions.catalyst.as_deref().map(&mut find);
+            let spawn = definition.spawn.as_deref().map(&mut find);
+            let inputs = if third.is_some() { 3 } else { 2 };
+            if products.is_empty() || products.len() > inputs {
+                problems.push(format!("{} needs between 1 and {} products", description, inputs));
+            }
+            if !(0.0..=1.0).contains(&definition.probability) {
+                problems.push(format!("{} has probability {} outside 0..=1", description, definition.probability));
+            }
+            if let (Some(min), Some(max)) = (definition.conditions.min_temperature, definition.conditions.max_temperature) {
+                if min > max {
+                    problems.push(format!("{} has an empty temperature range", description));
                 }
-            ],
-            VoxelMaterial::Lava => vec![
-                MaterialReactions{
-                    other_material: VoxelMaterial::Water,
-                    first_product: VoxelMaterial::Stone,
-                    second_product: VoxelMaterial::Steam,
-                    probability: 1.0
-                },
-                MaterialReactions{
-                    other_material: VoxelMaterial::Oil,
-                    first_product: VoxelMaterial::Lava,
-                    second_product: VoxelMaterial::Fire,
-                    probability: 1.0
-                },
-                MaterialReactions{
-                    other_material: VoxelMaterial::Ice,
-                    first_product: VoxelMaterial::Stone,
-                    second_product: VoxelMaterial::Steam,
-                    probability: 1.0
-                },
-                MaterialReactions{
-                    other_material: VoxelMaterial::SaltWater,
-                    first_product: VoxelMaterial::Stone,
-                    second_product: VoxelMaterial::Steam,
-                    probability: 1.0
-                },
-                MaterialReactions{
-                    other_material: VoxelMaterial::Wood,
-                    first_product: VoxelMaterial::Lava,
-                    second_product: VoxelMaterial::Fire,
-                    probability: 0.9
-                },
-            ],
-            VoxelMaterial::Fire => vec![
-                MaterialReactions{ 
-                    other_material: VoxelMaterial::Air,
-                    first_product: VoxelMaterial::Air,
-                    second_product: VoxelMaterial::Air,
-                    probability: 0.03
-                },
-                MaterialReactions{ 
-                    other_material: VoxelMaterial::Wood,
-                    first_product: VoxelMaterial::Fire,
-                    second_product: VoxelMaterial::Fire,
-                    probability: 0.6
-                },
-                MaterialReactions{ 
-                    other_material: VoxelMaterial::Oil,
-                    first_product: VoxelMaterial::Fire,
-                    second_product: VoxelMaterial::Fire,
-                    probability: 1.0
-                },
-                MaterialReactions{ 
-                    other_material: VoxelMaterial::Water,
-                    first_product: VoxelMaterial::Air,
-                    second_product: VoxelMaterial::Steam,
-                    probability: 1.0
-                },
-                MaterialReactions{ 
-                    other_material: VoxelMaterial::SaltWater,
-                    first_product: VoxelMaterial::Air,
-                    second_product: VoxelMaterial::Steam,
-                    probability: 1.0
-                },
-                MaterialReactions{ 
-                    other_material: VoxelMaterial::Ice,
-                    first_product: VoxelMaterial::Air,
-                    second_product: VoxelMaterial::Water,
-                    probability: 1.0
-                },
-            ],
-            VoxelMaterial::Ice => vec![
-                MaterialReactions{ 
-                    other_material: VoxelMaterial::Water,
-                    first_product: VoxelMaterial::Ice,
-                    second_product: VoxelMaterial::Ice,
-                    probability: 0.02
+            }
+            if definition.conditions.min_count == 0 || definition.conditions.min_count > 26 {
+                problems.push(format!("{} needs a minimum count between 1 and 26", description));
+            }
+
+            let (Some(reactant), Some(other)) = (reactant, other) else {
+                continue;
+            };
+            if products.is_empty() || products.iter().any(|i| i.is_none()) ||
+                matches!(third, Some(None)) || matches!(catalyst, Some(None)) || matches!(spawn, Some(None)) {
+                continue;
+            }
+            let product = |i: usize| products.get(i).copied().flatten().unwrap_or(VoxelMaterial::AIR);
+            let slot = &mut table[reactant.get_id() as usize*material_count + other.get_id() as usize];
+            if slot.is_some() {
+                problems.push(format!("{} duplicates an earlier reaction for the same pair", description));
+                continue;
+            }
+            *slot = Some(Reaction {
+                third: third.flatten(),
+                first_product: product(0),
+                second_product: product(1),
+                third_product: product(2),
+                probability: definition.probability,
+                conditions: ReactionConditions {
+                    min_temperature: definition.conditions.min_temperature,
+                    max_temperature: definition.conditions.max_temperature,
+                    catalyst: catalyst.flatten(),
+                    min_count: definition.conditions.min_count
                 },
-            ],
-            _ => vec![],
+                spawn: spawn.flatten()
+            });
+            reactive[reactant.get_id() as usize] = true;
         }
+        if !problems.is_empty() {
+            return Err(ReactionError::Invalid(problems));
+        }
+
+        Ok(Self { material_count, table, reactive })
+    }
+
+    pub fn parse_default(materials: &MaterialRegistry) -> Result<Self, ReactionError> {
+        Self::parse(DEFAULT_REACTIONS, materials)
+    }
+
+    pub fn load(path: &Path, materials: &MaterialRegistry) -> Result<Self, ReactionError> {
+        Self::parse(&fs::read_to_string(path)?, materials)
+    }
+
+    pub fn install(reactions: MaterialReactions) -> Result<(), ReactionError> {
+        REACTIONS.set(reactions).map_err(|_| ReactionError::AlreadyInstalled)
     }
-}
\ No newline at end of file
+
+    pub fn get() -> &'static MaterialReactions {
+        REACTIONS.get_or_init(|| Self::parse_default(MaterialRegistry::get()).unwrap())
+    }
+
+    pub fn has_reactions(&self, material: VoxelMaterial) -> bool {
+        self.reactive[material.get_id() as usize]
+    }
+
+    pub fn get_reaction(&self, material: VoxelMaterial, other: VoxelMaterial) -> Option<&Reaction> {
+        self.table[material.get_id() as usize*self.material_count + other.get_id() as usize].as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{MaterialReactions, ReactionError};
+    use crate::{material_registry::MaterialRegistry, model::VoxelMaterial};
+
+    #[test]
+    fn test_lookup_table() {
+        let reactions = MaterialReactions::get();
+        let salt = VoxelMaterial::from_name("Salt").unwrap();
+        let water = VoxelMaterial::from_name("Water").unwrap();
+        let reaction = reactions.get_reaction(salt, water).unwrap();
+        assert_eq!(reaction.first_product, VoxelMaterial::AIR);
+        assert_eq!(reaction.second_product, VoxelMaterial::from_name("Salt Water").unwrap());
+        assert!(reactions.has_reactions(salt));
+        assert!(reactions.get_reaction(water, salt).is_none());
+        assert!(!reactions.has_reactions(water));
+
+        let sand = VoxelMaterial::from_name("Sand").unwrap();
+        let mud = reactions.get_reaction(sand, water).unwrap();
+        assert!(!mud.conditions.is_met(20.0));
+        assert!(mud.conditions.is_met(50.0));
+    }
+
+    #[test]
+    fn test_validation() {
+        let source = r#"[
+            (reactant: "Salt", other: "Water", products: ["Air", "Salt Water"], probability: 0.1),
+            (reactant: "Salt", other: "Water", products: ["Salt", "Water"], probability: 0.5),
+            (reactant: "Salt", other: "Clay", products: ["Salt", "Sand"], probability: 0.5),
+            (reactant: "Sand", other: "Water", products: ["Sand", "Water"], probability: 1.5),
+            (reactant: "Ice", other: "Air", products: ["Water", "Air"], probability: 1.0, conditions: (min_temperature: Some(10.0), max_temperature: Some(0.0))),
+            (reactant: "Ice", other: "Sand", products: ["Water", "Air", "Stone"], probability: 1.0),
+            (reactant: "Ice", other: "Salt", products: ["Water"], probability: 1.0, conditions: (min_count: 0, catalyst: Some("Heat"))),
+        ]"#;
+        let Err(ReactionError::Invalid(problems)) = MaterialReactions::parse(source, MaterialRegistry::get()) else {
+            panic!("invalid reactions were accepted");
+        };
+        assert_eq!(problems.len(), 7);
+        assert!(problems[0].contains("duplicates"));
+        assert!(problems[1].contains("'Clay'"));
+        assert!(problems[2].contains("1.5"));
+        assert!(problems[3].contains("temperature"));
+        assert!(problems[4].contains("between 1 and 2 products"));
+        assert!(problems[5].contains("'Heat'"));
+        assert!(problems[6].contains("minimum count"));
+
+        assert!(matches!(MaterialReactions::parse("[(", MaterialRegistry::get()), Err(ReactionError::Parse(_))));
+    }
+}
@@ -0,0 +1,368 @@
+use std::{collections::HashMap, fmt::Display, fs, io, path::Path, sync::OnceLock};
+
+use serde::Deserialize;
+
+use crate::{chunk::CHUNK_SIZE, material_properties::{Combustion, Lifetime, MaterialProperties, MaterialType, PhaseTransition}, model::{VoxelMaterial, AMBIENT_TEMPERATURE}};
+
+const DEFAULT_MATERIALS: &str = include_str!("../materials.ron");
+const MAX_MATERIALS: usize = 256;
+const MAX_NAME_LENGTH: usize = 255;
+// a liquid spreading sideways has to stay within the chunks next to its own
+const MAX_FLOW_RATE: u32 = CHUNK_SIZE as u32 - 1;
+// steeper or flatter angles would let a sliding grain leave the chunks next to its own
+const MIN_REPOSE_ANGLE: f32 = 10.0;
+const MAX_REPOSE_ANGLE: f32 = 80.0;
+
+static REGISTRY: OnceLock<MaterialRegistry> = OnceLock::new();
+
+#[derive(Debug)]
+pub enum MaterialRegistryError {
+    Io(io::Error),
+    Parse(String),
+    Invalid(String),
+    AlreadyInstalled,
+}
+impl Display for MaterialRegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Parse(message) => write!(f, "Invalid materials file: {}", message),
+            Self::Invalid(message) => write!(f, "Invalid material definition: {}", message),
+            Self::AlreadyInstalled => write!(f, "Materials are already in use"),
+        }
+    }
+}
+impl From<io::Error> for MaterialRegistryError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+fn default_initial_temperature() -> f32 {
+    AMBIENT_TEMPERATURE
+}
+
+fn default_flow_rate() -> u32 {
+    1
+}
+
+fn default_repose_angle() -> f32 {
+    45.0
+}
+
+fn default_placeable() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct TransitionDefinition{
+    temperature: f32,
+    product: String
+}
+
+#[derive(Deserialize)]
+struct LifetimeDefinition{
+    min: u16,
+    max: u16,
+    on_expire: String
+}
+
+#[derive(Deserialize)]
+struct CombustionDefinition{
+    flammability: f32,
+    ignition_temperature: f32,
+    burn_duration: u16,
+    burn_temperature: f32,
+    flame: String,
+    residue: String
+}
+
+#[derive(Deserialize)]
+struct MaterialDefinition{
+    name: String,
+    material_type: MaterialType,
+    weight: u32,
+    activity: f32,
+    #[serde(default = "default_flow_rate")]
+    flow_rate: u32,
+    #[serde(default)]
+    viscosity: f32,
+    #[serde(default = "default_repose_angle")]
+    repose_angle: f32,
+    #[serde(default)]
+    wet_repose_angle: Option<f32>,
+    #[serde(default)]
+    strength: u32,
+    #[serde(default)]
+    rubble: Option<String>,
+    conductivity: f32,
+    heat_capacity: f32,
+    #[serde(default = "default_initial_temperature")]
+    initial_temperature: f32,
+    #[serde(default)]
+    heat_generation: f32,
+    #[serde(default)]
+    heating_transition: Option<TransitionDefinition>,
+    #[serde(default)]
+    cooling_transition: Option<TransitionDefinition>,
+    #[serde(default)]
+    lifetime: Option<LifetimeDefinition>,
+    #[serde(default)]
+    combustion: Option<CombustionDefinition>,
+    #[serde(default)]
+    texture: Option<String>,
+    color: (u8, u8, u8),
+    #[serde(default = "default_placeable")]
+    placeable: bool
+}
+
+pub struct MaterialEntry{
+    pub name: String,
+    pub texture_path: Option<String>,
+    pub color: (u8, u8, u8),
+    pub placeable: bool,
+    pub properties: MaterialProperties
+}
+
+pub struct MaterialRegistry{
+    entries: Vec<MaterialEntry>,
+    names: HashMap<String, VoxelMaterial>
+}
+impl MaterialRegistry {
+    pub fn parse(source: &str) -> Result<Self, MaterialRegistryError> {
+        let definitions: Vec<MaterialDefinition> = ron::from_str(source)
+            .map_err(|e| MaterialRegistryError::Parse(e.to_string()))?;
+        if definitions.len() > MAX_MATERIALS {
+            return Err(MaterialRegistryError::Invalid(format!("more than {} materials", MAX_MATERIALS)));
+        }
+        match definitions.first() {
+            Some(air) if air.name == "Air" && matches!(air.material_type, MaterialType::GAS) => {},
+            _ => return Err(MaterialRegistryError::Invalid("the first material must be the Air gas".to_string())),
+        }
+
+        if !definitions.iter().any(|i| i.placeable) {
+            return Err(MaterialRegistryError::Invalid("no placeable materials".to_string()));
+        }
+
+        let mut names = HashMap::new();
+        for (id, definition) in definitions.iter().enumerate() {
+            if definition.name.is_empty() || definition.name.len() > MAX_NAME_LENGTH {
+                return Err(MaterialRegistryError::Invalid(format!("invalid name '{}'", definition.name)));
+            }
+            if names.insert(definition.name.clone(), VoxelMaterial::from_id(id as u8)).is_some() {
+                return Err(MaterialRegistryError::Invalid(format!("duplicate material '{}'", definition.name)));
+            }
+            if definition.heat_capacity <= 0.0 {
+                return Err(MaterialRegistryError::Invalid(format!("{} needs a positive heat capacity", definition.name)));
+            }
+            if !(0.0..=1.0).contains(&definition.activity) {
+                return Err(MaterialRegistryError::Invalid(format!("{} activity must be between 0 and 1", definition.name)));
+            }
+            if definition.flow_rate == 0 || definition.flow_rate > MAX_FLOW_RATE {
+                return Err(MaterialRegistryError::Invalid(format!("{} flow rate must be between 1 and {}", definition.name, MAX_FLOW_RATE)));
+            }
+            if !(0.0..=1.0).contains(&definition.viscosity) {
+                return Err(MaterialRegistryError::Invalid(format!("{} viscosity must be between 0 and 1", definition.name)));
+            }
+            if definition.lifetime.as_ref().is_some_and(|i| i.min == 0 || i.min > i.max) {
+                return Err(MaterialRegistryError::Invalid(format!("{} lifetime needs 0 < min <= max", definition.name)));
+            }
+            if let Some(combustion) = &definition.combustion {
+                if !(0.0..=1.0).contains(&combustion.flammability) || combustion.burn_duration == 0 {
+                    return Err(MaterialRegistryError::Invalid(format!("{} needs a flammability between 0 and 1 and a positive burn duration", definition.name)));
+                }
+                // both keep their state in the voxel's age
+                if definition.lifetime.is_some() {
+                    return Err(MaterialRegistryError::Invalid(format!("{} can't have both a lifetime and combustion", definition.name)));
+                }
+            }
+            for angle in [Some(definition.repose_angle), definition.wet_repose_angle].into_iter().flatten() {
+                if !(MIN_REPOSE_ANGLE..=MAX_REPOSE_ANGLE).contains(&angle) {
+                    return Err(MaterialRegistryError::Invalid(format!("{} angle of repose must be between {} and {} degrees", definition.name, MIN_REPOSE_ANGLE, MAX_REPOSE_ANGLE)));
+                }
+            }
+        }
+
+        let resolve = |transition: &Option<TransitionDefinition>, name: &str| -> Result<Option<PhaseTransition>, MaterialRegistryError> {
+            let Some(transition) = transition else {
+                return Ok(None);
+            };
+            let product = names.get(&transition.product).copied().ok_or_else(|| MaterialRegistryError::Invalid(
+                format!("{} transitions into unknown material '{}'", name, transition.product)
+            ))?;
+            Ok(Some(PhaseTransition { temperature: transition.temperature, product }))
+        };
+        let resolve_lifetime = |definition: &MaterialDefinition| -> Result<Option<Lifetime>, MaterialRegistryError> {
+            let Some(lifetime) = &definition.lifetime else {
+                return Ok(None);
+            };
+            let on_expire = names.get(&lifetime.on_expire).copied().ok_or_else(|| MaterialRegistryError::Invalid(
+                format!("{} expires into unknown material '{}'", definition.name, lifetime.on_expire)
+            ))?;
+            Ok(Some(Lifetime { min: lifetime.min, max: lifetime.max, on_expire }))
+        };
+        let resolve_combustion = |definition: &MaterialDefinition| -> Result<Option<Combustion>, MaterialRegistryError> {
+            let Some(combustion) = &definition.combustion else {
+                return Ok(None);
+            };
+            let find = |name: &String| names.get(name).copied().ok_or_else(|| MaterialRegistryError::Invalid(
+                format!("{} burns into unknown material '{}'", definition.name, name)
+            ));
+            Ok(Some(Combustion {
+                flammability: combustion.flammability,
+                ignition_temperature: combustion.ignition_temperature,
+                burn_duration: combustion.burn_duration,
+                burn_temperature: combustion.burn_temperature,
+                flame: find(&combustion.flame)?,
+                residue: find(&combustion.residue)?
+            }))
+        };
+        let resolve_rubble = |definition: &MaterialDefinition| -> Result<Option<VoxelMaterial>, MaterialRegistryError> {
+            let Some(rubble) = &definition.rubble else {
+                return Ok(None);
+            };
+            let powder = names.get(rubble)
+                .map(|i| &definitions[i.get_id() as usize])
+                .filter(|i| matches!(i.material_type, MaterialType::POWDER));
+            if !matches!(definition.material_type, MaterialType::SOLID) || powder.is_none() {
+                return Err(MaterialRegistryError::Invalid(format!("{} can't collapse into '{}', only solids collapse into a powder", definition.name, rubble)));
+            }
+            Ok(Some(names[rubble]))
+        };
+        let mut entries = vec![];
+        for definition in &definitions {
+            let properties = MaterialProperties {
+                material_type: definition.material_type,
+                weight: definition.weight,
+                activity: definition.activity,
+                flow_rate: definition.flow_rate,
+                viscosity: definition.viscosity,
+                repose_angle: definition.repose_angle,
+                wet_repose_angle: definition.wet_repose_angle.unwrap_or(definition.repose_angle),
+                strength: definition.strength,
+                rubble: resolve_rubble(definition)?,
+                conductivity: definition.conductivity,
+                heat_capacity: definition.heat_capacity,
+                initial_temperature: definition.initial_temperature,
+                heat_generation: definition.heat_generation,
+                heating_transition: resolve(&definition.heating_transition, &definition.name)?,
+                cooling_transition: resolve(&definition.cooling_transition, &definition.name)?,
+                lifetime: resolve_lifetime(definition)?,
+                combustion: resolve_combustion(definition)?
+            };
+            entries.push(MaterialEntry {
+                name: definition.name.clone(),
+                texture_path: definition.texture.clone(),
+                color: definition.color,
+                placeable: definition.placeable,
+                properties
+            });
+        }
+
+        Ok(Self { entries, names })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MaterialRegistryError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn install(registry: MaterialRegistry) -> Result<(), MaterialRegistryError> {
+        REGISTRY.set(registry).map_err(|_| MaterialRegistryError::AlreadyInstalled)
+    }
+
+    pub fn get() -> &'static MaterialRegistry {
+        REGISTRY.get_or_init(|| Self::parse(DEFAULT_MATERIALS).unwrap())
+    }
+
+    pub fn get_materials(&self) -> Vec<VoxelMaterial> {
+        (0..self.entries.len()).map(|i| VoxelMaterial::from_id(i as u8)).collect()
+    }
+
+    pub fn get_material_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get_entry(&self, material: VoxelMaterial) -> &MaterialEntry {
+        &self.entries[material.get_id() as usize]
+    }
+
+    pub fn find(&self, name: &str) -> Option<VoxelMaterial> {
+        self.names.get(name).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{MaterialRegistry, MaterialRegistryError};
+    use crate::material_properties::MaterialType;
+
+    #[test]
+    fn test_default_materials() {
+        let registry = MaterialRegistry::get();
+        let water = registry.find("Water").unwrap();
+        let entry = registry.get_entry(water);
+        assert_eq!(entry.texture_path.as_deref(), Some("textures/water.png"));
+        assert!(matches!(entry.properties.material_type, MaterialType::LIQUID));
+        let steam = entry.properties.heating_transition.unwrap().product;
+        assert_eq!(registry.get_entry(steam).name, "Steam");
+        assert!(!registry.get_entry(registry.get_materials()[0]).placeable);
+
+        let wood = registry.get_entry(registry.find("Wood").unwrap()).properties.combustion.unwrap();
+        let oil = registry.get_entry(registry.find("Oil").unwrap()).properties.combustion.unwrap();
+        assert!(oil.burn_duration < wood.burn_duration);
+        assert!(oil.burn_temperature > wood.burn_temperature);
+        assert_eq!(registry.get_entry(wood.residue).name, "Ash");
+    }
+
+    #[test]
+    fn test_custom_materials() {
+        let registry = MaterialRegistry::parse(r#"[
+            (name: "Air", material_type: GAS, weight: 0, activity: 0.0, conductivity: 0.05, heat_capacity: 1.0, color: (0, 0, 0), placeable: false),
+            (name: "Mud", material_type: LIQUID, weight: 7, activity: 0.3, conductivity: 0.3, heat_capacity: 2.0, color: (90, 60, 30),
+                heating_transition: Some((temperature: 120.0, product: "Dirt")), lifetime: Some((min: 50, max: 60, on_expire: "Dirt"))),
+            (name: "Dirt", material_type: POWDER, weight: 8, activity: 0.5, conductivity: 0.2, heat_capacity: 1.0, color: (110, 80, 50)),
+        ]"#).unwrap();
+
+        assert_eq!(registry.get_material_count(), 3);
+        let mud = registry.find("Mud").unwrap();
+        assert_eq!(mud.get_id(), 1);
+        let properties = registry.get_entry(mud).properties;
+        assert_eq!(properties.initial_temperature, 20.0);
+        assert_eq!(properties.flow_rate, 1);
+        assert_eq!(properties.viscosity, 0.0);
+        assert_eq!(registry.get_entry(registry.find("Dirt").unwrap()).properties.get_repose_step(true), (1, 1));
+        assert_eq!(properties.strength, 0);
+        assert_eq!(properties.get_transition(130.0), registry.find("Dirt"));
+        let lifetime = properties.lifetime.unwrap();
+        assert_eq!(Some(lifetime.on_expire), registry.find("Dirt"));
+        assert_eq!(lifetime.get_expire_chance(49), 0.0);
+        assert_eq!(lifetime.get_expire_chance(50), 1.0 / 11.0);
+        assert_eq!(lifetime.get_expire_chance(60), 1.0);
+        assert!(registry.get_entry(mud).placeable);
+    }
+
+    #[test]
+    fn test_invalid_materials() {
+        let air = r#"(name: "Air", material_type: GAS, weight: 0, activity: 0.0, conductivity: 0.05, heat_capacity: 1.0, color: (0, 0, 0))"#;
+        let sand = r#"(name: "Sand", material_type: POWDER, weight: 10, activity: 1.0, conductivity: 0.2, heat_capacity: 0.8, color: (1, 2, 3))"#;
+        let invalid = [
+            "[".to_string(),
+            format!("[{}]", sand),
+            format!("[{}, {}, {}]", air, sand, sand),
+            format!("[{}, {}]", air, sand.replace("0.8", "0.0")),
+            format!("[{}, {}]", air, sand.replace("activity: 1.0", "activity: 1.0, flow_rate: 16")),
+            format!("[{}, {}]", air, sand.replace("activity: 1.0", "activity: 1.0, viscosity: 1.5")),
+            format!("[{}, {}]", air, sand.replace("activity: 1.0", "activity: 1.0, wet_repose_angle: Some(90.0)")),
+            format!("[{}, {}]", air, sand.replace("(1, 2, 3)", "(1, 2, 3), lifetime: Some((min: 10, max: 5, on_expire: \"Air\"))")),
+            format!("[{}, {}]", air, sand.replace("(1, 2, 3)", "(1, 2, 3), lifetime: Some((min: 5, max: 10, on_expire: \"Dust\"))")),
+            format!("[{}, {}]", air, sand.replace("activity: 1.0", "activity: 1.0, rubble: Some(\"Sand\")")),
+            format!("[{}, {}]", air, sand.replace("(1, 2, 3)", "(1, 2, 3), combustion: Some((flammability: 2.0, ignition_temperature: 300.0, burn_duration: 10, burn_temperature: 700.0, flame: \"Air\", residue: \"Air\"))")),
+            format!("[{}, {}]", air, sand.replace("(1, 2, 3)", "(1, 2, 3), combustion: Some((flammability: 0.5, ignition_temperature: 300.0, burn_duration: 10, burn_temperature: 700.0, flame: \"Fire\", residue: \"Air\"))")),
+            format!("[{}, {}]", air, sand.replace("(1, 2, 3)", "(1, 2, 3), cooling_transition: Some((temperature: 0.0, product: \"Glass\"))")),
+        ];
+        assert!(matches!(MaterialRegistry::parse(&invalid[0]), Err(MaterialRegistryError::Parse(_))));
+        for i in &invalid[1..] {
+            assert!(matches!(MaterialRegistry::parse(i), Err(MaterialRegistryError::Invalid(_))), "{}", i);
+        }
+    }
+}
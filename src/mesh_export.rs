@@ -0,0 +1,387 @@
+use std::{fmt::Display, fs::File, io::{self, BufWriter, Write}, ops::Range, path::Path};
+
+use crate::model::{VoxelMaterial, VoxelNeighbours, World};
+
+struct Face{
+    normal: [f32; 3],
+    corners: [[f32; 3]; 4],
+    is_exposed: fn(&VoxelNeighbours) -> bool
+}
+
+const FACES: [Face; 6] = [
+    Face{
+        normal: [1.0, 0.0, 0.0],
+        corners: [[1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [1.0, 1.0, 1.0], [1.0, 0.0, 1.0]],
+        is_exposed: |neighbours| !neighbours.right
+    },
+    Face{
+        normal: [-1.0, 0.0, 0.0],
+        corners: [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 1.0], [0.0, 1.0, 0.0]],
+        is_exposed: |neighbours| !neighbours.left
+    },
+    Face{
+        normal: [0.0, 1.0, 0.0],
+        corners: [[0.0, 1.0, 0.0], [0.0, 1.0, 1.0], [1.0, 1.0, 1.0], [1.0, 1.0, 0.0]],
+        is_exposed: |neighbours| !neighbours.top
+    },
+    Face{
+        normal: [0.0, -1.0, 0.0],
+        corners: [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0, 1.0], [0.0, 0.0, 1.0]],
+        is_exposed: |neighbours| !neighbours.bottom
+    },
+    Face{
+        normal: [0.0, 0.0, 1.0],
+        corners: [[0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [1.0, 1.0, 1.0], [0.0, 1.0, 1.0]],
+        is_exposed: |neighbours| !neighbours.front
+    },
+    Face{
+        normal: [0.0, 0.0, -1.0],
+        corners: [[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0], [1.0, 0.0, 0.0]],
+        is_exposed: |neighbours| !neighbours.back
+    },
+];
+
+const FACE_UVS: [[f32; 2]; 4] = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+
+#[derive(Debug)]
+pub enum MeshExportError {
+    Io(io::Error),
+    UnsupportedFormat(String),
+}
+impl Display for MeshExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::UnsupportedFormat(extension) => write!(f, "Unsupported mesh format '{}', expected obj, ply or glb", extension),
+        }
+    }
+}
+impl From<io::Error> for MeshExportError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+pub struct MeshGroup{
+    pub material: VoxelMaterial,
+    pub indices: Range<usize>
+}
+
+pub struct SurfaceMesh{
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+    pub groups: Vec<MeshGroup>
+}
+impl SurfaceMesh {
+    pub fn extract(world: &World, voxel_size: f32) -> Self {
+        let mut mesh = SurfaceMesh { positions: vec![], normals: vec![], uvs: vec![], indices: vec![], groups: vec![] };
+        let mut occupied = world.get_occupied();
+        occupied.sort_by_key(|(x, y, z)| world.get(*x, *y, *z).get_id());
+
+        for (x, y, z) in occupied {
+            let material = world.get(x, y, z);
+            let neighbours = world.get_neighbours(x, y, z);
+            let start = mesh.indices.len();
+            for face in &FACES {
+                if !(face.is_exposed)(&neighbours) {
+                    continue;
+                }
+                let first = mesh.positions.len() as u32;
+                for (corner, uv) in face.corners.iter().zip(FACE_UVS) {
+                    mesh.positions.push([
+                        (x as f32 + corner[0]) * voxel_size,
+                        (y as f32 + corner[1]) * voxel_size,
+                        (z as f32 + corner[2]) * voxel_size
+                    ]);
+                    mesh.normals.push(face.normal);
+                    mesh.uvs.push(uv);
+                }
+                mesh.indices.extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+            }
+
+            match mesh.groups.last_mut() {
+                Some(group) if group.material.get_id() == material.get_id() => group.indices.end = mesh.indices.len(),
+                _ => mesh.groups.push(MeshGroup { material, indices: start..mesh.indices.len() }),
+            }
+        }
+
+        mesh
+    }
+
+    pub fn get_triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+}
+
+fn material_name(material: &VoxelMaterial) -> String {
+    material.to_string().replace(' ', "_")
+}
+
+fn color_to_f32(color: (u8, u8, u8)) -> [f32; 3] {
+    [color.0 as f32 / 255.0, color.1 as f32 / 255.0, color.2 as f32 / 255.0]
+}
+
+pub fn write_obj(mesh: &SurfaceMesh, mtl_file_name: &str, writer: &mut impl Write) -> Result<(), MeshExportError> {
+    writeln!(writer, "mtllib {}", mtl_file_name)?;
+    for i in &mesh.positions {
+        writeln!(writer, "v {} {} {}", i[0], i[1], i[2])?;
+    }
+    for i in &mesh.uvs {
+        writeln!(writer, "vt {} {}", i[0], i[1])?;
+    }
+    for i in &mesh.normals {
+        writeln!(writer, "vn {} {} {}", i[0], i[1], i[2])?;
+    }
+    for group in &mesh.groups {
+        writeln!(writer, "usemtl {}", material_name(&group.material))?;
+        for triangle in mesh.indices[group.indices.clone()].chunks_exact(3) {
+            let (a, b, c) = (triangle[0] + 1, triangle[1] + 1, triangle[2] + 1);
+            writeln!(writer, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn write_mtl(mesh: &SurfaceMesh, writer: &mut impl Write) -> Result<(), MeshExportError> {
+    for group in &mesh.groups {
+        let [r, g, b] = color_to_f32(group.material.get_color());
+        writeln!(writer, "newmtl {}", material_name(&group.material))?;
+        writeln!(writer, "Kd {} {} {}", r, g, b)?;
+        if let Some(path) = group.material.get_texture_path() {
+            writeln!(writer, "map_Kd {}", path)?;
+        }
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+pub fn write_ply(mesh: &SurfaceMesh, writer: &mut impl Write) -> Result<(), MeshExportError> {
+    let mut colors = vec![(0, 0, 0); mesh.positions.len()];
+    for group in &mesh.groups {
+        for i in &mesh.indices[group.indices.clone()] {
+            colors[*i as usize] = group.material.get_color();
+        }
+    }
+
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format ascii 1.0")?;
+    writeln!(writer, "element vertex {}", mesh.positions.len())?;
+    for property in ["x", "y", "z", "nx", "ny", "nz", "s", "t"] {
+        writeln!(writer, "property float {}", property)?;
+    }
+    for property in ["red", "green", "blue"] {
+        writeln!(writer, "property uchar {}", property)?;
+    }
+    writeln!(writer, "element face {}", mesh.get_triangle_count())?;
+    writeln!(writer, "property list uchar uint vertex_indices")?;
+    writeln!(writer, "end_header")?;
+    for (i, ((position, normal), uv)) in mesh.positions.iter().zip(&mesh.normals).zip(&mesh.uvs).enumerate() {
+        writeln!(
+            writer,
+            "{} {} {} {} {} {} {} {} {} {} {}",
+            position[0], position[1], position[2],
+            normal[0], normal[1], normal[2],
+            uv[0], uv[1],
+            colors[i].0, colors[i].1, colors[i].2
+        )?;
+    }
+    for triangle in mesh.indices.chunks_exact(3) {
+        writeln!(writer, "3 {} {} {}", triangle[0], triangle[1], triangle[2])?;
+    }
+
+    Ok(())
+}
+
+fn push_f32s(buffer: &mut Vec<u8>, values: &[f32]) {
+    for i in values {
+        buffer.extend(i.to_le_bytes());
+    }
+}
+
+pub fn write_glb(mesh: &SurfaceMesh, writer: &mut impl Write) -> Result<(), MeshExportError> {
+    let vertex_count = mesh.positions.len();
+    let mut binary = vec![];
+    for i in &mesh.positions {
+        push_f32s(&mut binary, i);
+    }
+    let normals_offset = binary.len();
+    for i in &mesh.normals {
+        push_f32s(&mut binary, i);
+    }
+    let uvs_offset = binary.len();
+    for i in &mesh.uvs {
+        push_f32s(&mut binary, i);
+    }
+    let indices_offset = binary.len();
+    for i in &mesh.indices {
+        binary.extend(i.to_le_bytes());
+    }
+
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for position in &mesh.positions {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(position[axis]);
+            max[axis] = max[axis].max(position[axis]);
+        }
+    }
+
+    let mut buffer_views = vec![
+        format!(r#"{{"buffer":0,"byteOffset":0,"byteLength":{},"target":34962}}"#, normals_offset),
+        format!(r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":34962}}"#, normals_offset, uvs_offset - normals_offset),
+        format!(r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":34962}}"#, uvs_offset, indices_offset - uvs_offset),
+    ];
+    let mut accessors = vec![
+        format!(
+            r#"{{"bufferView":0,"componentType":5126,"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+            vertex_count, min[0], min[1], min[2], max[0], max[1], max[2]
+        ),
+        format!(r#"{{"bufferView":1,"componentType":5126,"count":{},"type":"VEC3"}}"#, vertex_count),
+        format!(r#"{{"bufferView":2,"componentType":5126,"count":{},"type":"VEC2"}}"#, vertex_count),
+    ];
+    let mut primitives = vec![];
+    let mut materials = vec![];
+    for (i, group) in mesh.groups.iter().enumerate() {
+        buffer_views.push(format!(
+            r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":34963}}"#,
+            indices_offset + group.indices.start * 4, group.indices.len() * 4
+        ));
+        accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":5125,"count":{},"type":"SCALAR"}}"#,
+            buffer_views.len() - 1, group.indices.len()
+        ));
+        primitives.push(format!(
+            r#"{{"attributes":{{"POSITION":0,"NORMAL":1,"TEXCOORD_0":2}},"indices":{},"material":{}}}"#,
+            accessors.len() - 1, i
+        ));
+        let [r, g, b] = color_to_f32(group.material.get_color()).map(|c| c.powf(2.2));
+        materials.push(format!(
+            r#"{{"name":"{}","pbrMetallicRoughness":{{"baseColorFactor":[{},{},{},1.0],"metallicFactor":0.0}}}}"#,
+            material_name(&group.material), r, g, b
+        ));
+    }
+
+    let mut json = if vertex_count == 0 {
+        r#"{"asset":{"version":"2.0","generator":"sand_3d"},"scenes":[{"nodes":[]}],"scene":0}"#.to_string()
+    }else{
+        format!(
+            r#"{{"asset":{{"version":"2.0","generator":"sand_3d"}},"scenes":[{{"nodes":[0]}}],"scene":0,"nodes":[{{"mesh":0}}],"meshes":[{{"primitives":[{}]}}],"materials":[{}],"accessors":[{}],"bufferViews":[{}],"buffers":[{{"byteLength":{}}}]}}"#,
+            primitives.join(","), materials.join(","), accessors.join(","), buffer_views.join(","), binary.len()
+        )
+    }.into_bytes();
+    while json.len() % 4 != 0 {
+        json.push(b' ');
+    }
+    while binary.len() % 4 != 0 {
+        binary.push(0);
+    }
+
+    let has_binary = !binary.is_empty();
+    let total_length = 12 + 8 + json.len() + if has_binary { 8 + binary.len() } else { 0 };
+    writer.write_all(b"glTF")?;
+    writer.write_all(&2u32.to_le_bytes())?;
+    writer.write_all(&(total_length as u32).to_le_bytes())?;
+    writer.write_all(&(json.len() as u32).to_le_bytes())?;
+    writer.write_all(b"JSON")?;
+    writer.write_all(&json)?;
+    if has_binary {
+        writer.write_all(&(binary.len() as u32).to_le_bytes())?;
+        writer.write_all(b"BIN\0")?;
+        writer.write_all(&binary)?;
+    }
+
+    Ok(())
+}
+
+pub fn export_to_path(mesh: &SurfaceMesh, path: &Path) -> Result<(), MeshExportError> {
+    let extension = path.extension()
+        .and_then(|i| i.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let mut writer = BufWriter::new(File::create(path)?);
+    match extension.as_str() {
+        "obj" => {
+            let mtl_path = path.with_extension("mtl");
+            let mtl_file_name = mtl_path.file_name().unwrap().to_string_lossy().to_string();
+            write_obj(mesh, &mtl_file_name, &mut writer)?;
+            let mut mtl_writer = BufWriter::new(File::create(&mtl_path)?);
+            write_mtl(mesh, &mut mtl_writer)?;
+            mtl_writer.flush()?;
+        },
+        "ply" => write_ply(mesh, &mut writer)?,
+        "glb" => write_glb(mesh, &mut writer)?,
+        _ => return Err(MeshExportError::UnsupportedFormat(extension)),
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{write_glb, write_obj, write_ply, SurfaceMesh, FACES};
+    use crate::model::{VoxelMaterial, World};
+
+    #[test]
+    fn test_face_winding() {
+        for face in &FACES {
+            let [a, b, c, _] = face.corners;
+            let u = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+            let v = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+            let cross = [u[1]*v[2] - u[2]*v[1], u[2]*v[0] - u[0]*v[2], u[0]*v[1] - u[1]*v[0]];
+            assert_eq!(cross, face.normal);
+        }
+    }
+
+    #[test]
+    fn test_extract_surface() {
+        let mut world = World::new(4, 4, 4);
+        let mesh = SurfaceMesh::extract(&world, 1.0);
+        assert_eq!(mesh.get_triangle_count(), 0);
+
+        world.set(VoxelMaterial::from_name("Sand").unwrap(), 0, 0, 0);
+        let mesh = SurfaceMesh::extract(&world, 2.0);
+        assert_eq!(mesh.get_triangle_count(), 12);
+        assert_eq!(mesh.positions.len(), 24);
+        assert!(mesh.positions.iter().all(|i| i.iter().all(|c| *c == 0.0 || *c == 2.0)));
+
+        world.set(VoxelMaterial::from_name("Water").unwrap(), 1, 0, 0);
+        let mesh = SurfaceMesh::extract(&world, 1.0);
+        assert_eq!(mesh.get_triangle_count(), 20);
+        assert_eq!(mesh.groups.len(), 2);
+        assert_eq!(mesh.groups[0].material, VoxelMaterial::from_name("Sand").unwrap());
+        assert_eq!(mesh.groups[0].indices, 0..30);
+        assert_eq!(mesh.groups[1].indices, 30..60);
+    }
+
+    #[test]
+    fn test_writers() {
+        let mut world = World::new(3, 3, 3);
+        world.set(VoxelMaterial::from_name("Stone").unwrap(), 1, 1, 1);
+        world.set(VoxelMaterial::from_name("Salt Water").unwrap(), 1, 2, 1);
+        let mesh = SurfaceMesh::extract(&world, 1.0);
+
+        let mut obj = vec![];
+        write_obj(&mesh, "world.mtl", &mut obj).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        assert!(obj.starts_with("mtllib world.mtl\n"));
+        assert_eq!(obj.lines().filter(|i| i.starts_with("f ")).count(), mesh.get_triangle_count());
+        assert!(obj.contains("usemtl Salt_Water\n"));
+
+        let mut ply = vec![];
+        write_ply(&mesh, &mut ply).unwrap();
+        let ply = String::from_utf8(ply).unwrap();
+        assert!(ply.contains(&format!("element vertex {}\n", mesh.positions.len())));
+        assert!(ply.contains(&format!("element face {}\n", mesh.get_triangle_count())));
+
+        let mut glb = vec![];
+        write_glb(&mesh, &mut glb).unwrap();
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(u32::from_le_bytes([glb[8], glb[9], glb[10], glb[11]]) as usize, glb.len());
+        assert_eq!(glb.len() % 4, 0);
+    }
+}
@@ -1,61 +1,57 @@
-use std::fmt::Display;
-
-#[derive(Clone, Copy, Debug)]
-pub enum VoxelMaterial {
-    Air,
-    Sand,
-    Water,
-    Metal,
-    Salt,
-    SaltWater,
-    Steam,
-    Lava,
-    Stone,
-    Wood,
-    Fire,
-    Oil,
-    Ice
-}
+use std::{collections::HashSet, fmt::Display};
+
+use crate::{chunk::{Chunk, CHUNK_SIZE}, material_properties::{MaterialProperties, MaterialType}, material_registry::MaterialRegistry};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VoxelMaterial(u8);
 impl VoxelMaterial {
+    pub const AIR: VoxelMaterial = VoxelMaterial(0);
+
+    pub fn from_id(id: u8) -> Self {
+        Self(id)
+    }
+
+    pub fn get_all() -> Vec<VoxelMaterial> {
+        MaterialRegistry::get().get_materials()
+    }
+
+    pub fn from_name(name: &str) -> Option<VoxelMaterial> {
+        MaterialRegistry::get().find(name)
+    }
+
     pub fn get_id(&self) -> u32 {
-        match self {
-            Self::Air => 0,
-            Self::Sand => 1,
-            Self::Water => 2,
-            Self::Metal => 3,
-            Self::Salt => 4,
-            Self::SaltWater => 5,
-            Self::Steam => 6,
-            Self::Stone => 7,
-            Self::Lava => 8,
-            Self::Wood => 9,
-            Self::Fire => 10,
-            Self::Oil => 11,
-            Self::Ice => 12,
-        }
+        self.0 as u32
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        &MaterialRegistry::get().get_entry(*self).name
+    }
+
+    pub fn get_texture_path(&self) -> Option<&'static str> {
+        MaterialRegistry::get().get_entry(*self).texture_path.as_deref()
+    }
+
+    pub fn get_color(&self) -> (u8, u8, u8) {
+        MaterialRegistry::get().get_entry(*self).color
+    }
+
+    pub fn is_placeable(&self) -> bool {
+        MaterialRegistry::get().get_entry(*self).placeable
+    }
+
+    pub fn is_solid(&self) -> bool {
+        matches!(MaterialProperties::new(self).material_type, MaterialType::SOLID)
     }
 }
 impl Display for VoxelMaterial {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", match self {
-            Self::Air => "Air",
-            Self::Sand => "Sand",
-            Self::Water => "Water",
-            Self::Metal => "Metal",
-            Self::Salt =>  "Salt",
-            Self::SaltWater => "Salt Water",
-            Self::Steam => "Steam",
-            Self::Lava => "Lava",
-            Self::Stone => "Stone",
-            Self::Wood => "Wood",
-            Self::Fire => "Fire",
-            Self::Oil => "Oil",
-            Self::Ice => "Ice",
-        })
+        write!(f, "{}", self.get_name())
     }
 }
 
-pub const WORLD_SIZE: usize = 30;
+pub const AMBIENT_TEMPERATURE: f32 = 20.0;
+/// Largest supported extent along any axis.
+pub const MAX_WORLD_SIZE: usize = 1024;
 
 pub struct VoxelNeighbours{
     pub top: bool,
@@ -77,59 +73,345 @@ impl VoxelNeighbours {
 }
 
 pub struct World{
-    voxels: [VoxelMaterial; WORLD_SIZE*WORLD_SIZE*WORLD_SIZE]
+    width: usize,
+    height: usize,
+    depth: usize,
+    chunks_x: usize,
+    chunks_y: usize,
+    chunks_z: usize,
+    chunks: Vec<Option<Box<Chunk>>>,
+    structure_changed: HashSet<(usize, usize, usize)>
 }
 impl World {
 
-    pub fn new() -> Self {
-        let voxels = [VoxelMaterial::Air; WORLD_SIZE*WORLD_SIZE*WORLD_SIZE];
+    pub fn new(width: usize, height: usize, depth: usize) -> Self {
+        assert!(width > 0 && height > 0 && depth > 0, "World dimensions must be positive");
+        assert!(
+            width <= MAX_WORLD_SIZE && height <= MAX_WORLD_SIZE && depth <= MAX_WORLD_SIZE,
+            "World dimensions must not exceed {}", MAX_WORLD_SIZE
+        );
+        let chunks_x = width.div_ceil(CHUNK_SIZE);
+        let chunks_y = height.div_ceil(CHUNK_SIZE);
+        let chunks_z = depth.div_ceil(CHUNK_SIZE);
+        let mut chunks = Vec::new();
+        chunks.resize_with(chunks_x*chunks_y*chunks_z, || None);
+
+        Self { width, height, depth, chunks_x, chunks_y, chunks_z, chunks, structure_changed: HashSet::new() }
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get_depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn is_in_bounds(&self, x: usize, y: usize, z: usize) -> bool {
+        x < self.width && y < self.height && z < self.depth
+    }
+
+    pub fn get_allocated_chunk_count(&self) -> usize {
+        self.chunks.iter().filter(|i| i.is_some()).count()
+    }
+
+    pub fn is_chunk_allocated(&self, chunk_x: usize, chunk_y: usize, chunk_z: usize) -> bool {
+        self.chunks[self.chunk_coordinates_to_index(chunk_x, chunk_y, chunk_z)].is_some()
+    }
+
+    pub fn get_allocated_chunks(&self) -> Vec<(usize, usize, usize)> {
+        let mut allocated = vec![];
+        for chunk_y in 0..self.chunks_y {
+            for chunk_z in 0..self.chunks_z {
+                for chunk_x in 0..self.chunks_x {
+                    if self.is_chunk_allocated(chunk_x, chunk_y, chunk_z) {
+                        allocated.push((chunk_x, chunk_y, chunk_z));
+                    }
+                }
+            }
+        }
+
+        allocated
+    }
+
+    /// The given chunks and every chunk next to them that is allocated, in the order of `get_allocated_chunks`.
+    pub fn get_allocated_chunks_around(&self, chunks: &[(usize, usize, usize)]) -> Vec<(usize, usize, usize)> {
+        let mut around = vec![false; self.chunks.len()];
+        for (chunk_x, chunk_y, chunk_z) in chunks {
+            for y in chunk_y.saturating_sub(1)..=(chunk_y + 1).min(self.chunks_y - 1) {
+                for z in chunk_z.saturating_sub(1)..=(chunk_z + 1).min(self.chunks_z - 1) {
+                    for x in chunk_x.saturating_sub(1)..=(chunk_x + 1).min(self.chunks_x - 1) {
+                        around[self.chunk_coordinates_to_index(x, y, z)] = true;
+                    }
+                }
+            }
+        }
+
+        self.get_allocated_chunks()
+            .into_iter()
+            .filter(|(x, y, z)| around[self.chunk_coordinates_to_index(*x, *y, *z)])
+            .collect()
+    }
+
+    pub fn get_chunk_bounds(&self, chunk_x: usize, chunk_y: usize, chunk_z: usize) -> ((usize, usize), (usize, usize), (usize, usize)) {
+        let x = chunk_x*CHUNK_SIZE;
+        let y = chunk_y*CHUNK_SIZE;
+        let z = chunk_z*CHUNK_SIZE;
+        (
+            (x, (x + CHUNK_SIZE).min(self.width)),
+            (y, (y + CHUNK_SIZE).min(self.height)),
+            (z, (z + CHUNK_SIZE).min(self.depth))
+        )
+    }
+
+    pub fn begin_tick(&mut self) {
+        for chunk in self.chunks.iter_mut().flatten() {
+            chunk.begin_tick();
+        }
+    }
+
+    pub fn get_scheduled_chunks(&self) -> Vec<(usize, usize, usize)> {
+        self.get_allocated_chunks()
+            .into_iter()
+            .filter(|(x, y, z)| self.chunks[self.chunk_coordinates_to_index(*x, *y, *z)].as_ref().is_some_and(|i| i.is_scheduled()))
+            .collect()
+    }
+
+    /// Chunks that have been woken since the last tick began and will be simulated in the next one.
+    pub fn get_active_chunks(&self) -> Vec<(usize, usize, usize)> {
+        self.get_allocated_chunks()
+            .into_iter()
+            .filter(|(x, y, z)| self.chunks[self.chunk_coordinates_to_index(*x, *y, *z)].as_ref().is_some_and(|i| i.is_awake()))
+            .collect()
+    }
+
+    /// Wakes the chunk of a voxel and the chunks of its neighbours.
+    pub fn wake(&mut self, x: usize, y: usize, z: usize) {
+        for (chunk_x, chunk_y, chunk_z) in neighbour_chunks(x, y, z, (self.chunks_x, self.chunks_y, self.chunks_z)) {
+            let index = self.chunk_coordinates_to_index(chunk_x, chunk_y, chunk_z);
+            if let Some(chunk) = &mut self.chunks[index] {
+                chunk.wake();
+            }
+        }
+    }
+
+    /// Chunks in which a solid voxel was added or removed since the last call.
+    pub fn take_structure_changed(&mut self) -> HashSet<(usize, usize, usize)> {
+        std::mem::take(&mut self.structure_changed)
+    }
+
+    pub fn mark_structure_changed(&mut self, chunk_x: usize, chunk_y: usize, chunk_z: usize) {
+        self.structure_changed.insert((chunk_x, chunk_y, chunk_z));
+    }
+
+    /// Splits the world into regions around the given chunks, each owning its chunk and the 26 chunks
+    /// around it. Panics if two regions would share a chunk.
+    pub fn split_regions(&mut self, chunks: &[(usize, usize, usize)]) -> Vec<WorldRegion<'_>> {
+        let mut owners = vec![None; self.chunks.len()];
+        for (region, (chunk_x, chunk_y, chunk_z)) in chunks.iter().enumerate() {
+            for offset_y in 0..3 {
+                for offset_z in 0..3 {
+                    for offset_x in 0..3 {
+                        let (Some(x), Some(y), Some(z)) = ((chunk_x + offset_x).checked_sub(1), (chunk_y + offset_y).checked_sub(1), (chunk_z + offset_z).checked_sub(1)) else {
+                            continue;
+                        };
+                        if x >= self.chunks_x || y >= self.chunks_y || z >= self.chunks_z {
+                            continue;
+                        }
+                        let index = self.chunk_coordinates_to_index(x, y, z);
+                        assert!(owners[index].is_none(), "regions around chunks {:?} overlap", chunks);
+                        owners[index] = Some((region, WorldRegion::slot_index(offset_x, offset_y, offset_z)));
+                    }
+                }
+            }
+        }
+
+        let (width, height, depth) = (self.width, self.height, self.depth);
+        let mut regions: Vec<WorldRegion> = chunks.iter()
+            .map(|chunk| WorldRegion { width, height, depth, chunk: *chunk, slots: std::array::from_fn(|_| None), structure_changed: vec![] })
+            .collect();
+        for (slot, owner) in self.chunks.iter_mut().zip(owners) {
+            if let Some((region, index)) = owner {
+                regions[region].slots[index] = Some(slot);
+            }
+        }
+
+        regions
+    }
+
+    pub fn get_occupied(&self) -> Vec<(usize, usize, usize)> {
+        let mut occupied = vec![];
+        for (chunk_x, chunk_y, chunk_z) in self.get_allocated_chunks() {
+            let ((min_x, max_x), (min_y, max_y), (min_z, max_z)) = self.get_chunk_bounds(chunk_x, chunk_y, chunk_z);
+            for y in min_y..max_y {
+                for z in min_z..max_z {
+                    for x in min_x..max_x {
+                        if self.get(x, y, z) != VoxelMaterial::AIR {
+                            occupied.push((x, y, z));
+                        }
+                    }
+                }
+            }
+        }
 
-        Self { voxels }
+        occupied
+    }
+
+    pub fn get_material_counts(&self) -> Vec<(VoxelMaterial, usize)> {
+        let materials = VoxelMaterial::get_all();
+        let mut counts = vec![0; materials.len()];
+        for (x, y, z) in self.get_occupied() {
+            counts[self.get(x, y, z).get_id() as usize] += 1;
+        }
+
+        materials.into_iter()
+            .filter(|i| *i != VoxelMaterial::AIR)
+            .map(|i| (i, counts[i.get_id() as usize]))
+            .collect()
+    }
+
+    fn chunk_coordinates_to_index(&self, chunk_x: usize, chunk_y: usize, chunk_z: usize) -> usize {
+        chunk_x + chunk_z*self.chunks_x + chunk_y*self.chunks_x*self.chunks_z
+    }
+
+    fn chunk_index(&self, x: usize, y: usize, z: usize) -> usize {
+        self.chunk_coordinates_to_index(x / CHUNK_SIZE, y / CHUNK_SIZE, z / CHUNK_SIZE)
     }
 
     pub fn get(&self, x: usize, y: usize, z: usize) -> VoxelMaterial {
-        self.voxels[Self::coordiantes_to_index(x, y, z)]
+        debug_assert!(self.is_in_bounds(x, y, z));
+        match &self.chunks[self.chunk_index(x, y, z)] {
+            Some(chunk) => chunk.get(x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE),
+            None => VoxelMaterial::AIR,
+        }
     }
 
     pub fn get_index(&self, index: usize) -> VoxelMaterial {
-        self.voxels[index]
+        let (x, y, z) = self.index_to_coordinates(index);
+        self.get(x, y, z)
     }
 
-    pub fn coordiantes_to_index(x: usize, y: usize, z: usize) -> usize {
-        x + z*WORLD_SIZE + y*WORLD_SIZE*WORLD_SIZE
+    pub fn coordiantes_to_index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + z*self.width + y*self.width*self.depth
     }
 
-    pub fn index_to_coordinates(index: usize) -> (usize, usize, usize) {
-        let y = index / (WORLD_SIZE * WORLD_SIZE);
-        let r = index % (WORLD_SIZE * WORLD_SIZE);
-        let z = r / WORLD_SIZE;
-        let x = r % WORLD_SIZE;
+    pub fn index_to_coordinates(&self, index: usize) -> (usize, usize, usize) {
+        let y = index / (self.width * self.depth);
+        let r = index % (self.width * self.depth);
+        let z = r / self.width;
+        let x = r % self.width;
         (x, y, z)
     }
 
     pub fn set(&mut self, material: VoxelMaterial, x: usize, y: usize, z: usize) {
-        self.voxels[Self::coordiantes_to_index(x, y, z)] = material;
+        debug_assert!(self.is_in_bounds(x, y, z));
+        let chunk_index = self.chunk_index(x, y, z);
+        if set_in_slot(&mut self.chunks[chunk_index], material, x, y, z) {
+            self.mark_structure_changed(x / CHUNK_SIZE, y / CHUNK_SIZE, z / CHUNK_SIZE);
+        }
+        self.wake(x, y, z);
+    }
+
+    pub fn place(&mut self, material: VoxelMaterial, x: usize, y: usize, z: usize) {
+        self.set(material, x, y, z);
+        self.set_temperature(MaterialProperties::new(&material).initial_temperature, x, y, z);
+        self.set_velocity(0.0, x, y, z);
+        self.set_age(0, x, y, z);
+    }
+
+    pub fn get_temperature(&self, x: usize, y: usize, z: usize) -> f32 {
+        debug_assert!(self.is_in_bounds(x, y, z));
+        match &self.chunks[self.chunk_index(x, y, z)] {
+            Some(chunk) => chunk.get_temperature(x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE),
+            None => AMBIENT_TEMPERATURE,
+        }
+    }
+
+    pub fn set_temperature(&mut self, temperature: f32, x: usize, y: usize, z: usize) {
+        debug_assert!(self.is_in_bounds(x, y, z));
+        let chunk_index = self.chunk_index(x, y, z);
+        if let Some(chunk) = &mut self.chunks[chunk_index] {
+            chunk.set_temperature(temperature, x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE);
+        }
+    }
+
+    /// Vertical velocity in voxels per tick, negative when falling.
+    pub fn get_velocity(&self, x: usize, y: usize, z: usize) -> f32 {
+        debug_assert!(self.is_in_bounds(x, y, z));
+        match &self.chunks[self.chunk_index(x, y, z)] {
+            Some(chunk) => chunk.get_velocity(x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE),
+            None => 0.0,
+        }
+    }
+
+    pub fn set_velocity(&mut self, velocity: f32, x: usize, y: usize, z: usize) {
+        debug_assert!(self.is_in_bounds(x, y, z));
+        let chunk_index = self.chunk_index(x, y, z);
+        if let Some(chunk) = &mut self.chunks[chunk_index] {
+            chunk.set_velocity(velocity, x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE);
+        }
+    }
+
+    /// Ticks a voxel with a limited lifetime has existed, reset whenever the voxel's material changes.
+    pub fn get_age(&self, x: usize, y: usize, z: usize) -> u16 {
+        debug_assert!(self.is_in_bounds(x, y, z));
+        match &self.chunks[self.chunk_index(x, y, z)] {
+            Some(chunk) => chunk.get_age(x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE),
+            None => 0,
+        }
+    }
+
+    pub fn set_age(&mut self, age: u16, x: usize, y: usize, z: usize) {
+        debug_assert!(self.is_in_bounds(x, y, z));
+        let chunk_index = self.chunk_index(x, y, z);
+        if let Some(chunk) = &mut self.chunks[chunk_index] {
+            chunk.set_age(age, x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE);
+        }
+    }
+
+    pub fn swap(&mut self, x1: usize, y1: usize, z1: usize, x2: usize, y2: usize, z2: usize) {
+        let first = self.get(x1, y1, z1);
+        let first_temperature = self.get_temperature(x1, y1, z1);
+        let first_velocity = self.get_velocity(x1, y1, z1);
+        let first_age = self.get_age(x1, y1, z1);
+        let second = self.get(x2, y2, z2);
+        let second_temperature = self.get_temperature(x2, y2, z2);
+        let second_velocity = self.get_velocity(x2, y2, z2);
+        let second_age = self.get_age(x2, y2, z2);
+
+        self.set(first, x2, y2, z2);
+        self.set_temperature(first_temperature, x2, y2, z2);
+        self.set_velocity(first_velocity, x2, y2, z2);
+        self.set_age(first_age, x2, y2, z2);
+        self.set(second, x1, y1, z1);
+        self.set_temperature(second_temperature, x1, y1, z1);
+        self.set_velocity(second_velocity, x1, y1, z1);
+        self.set_age(second_age, x1, y1, z1);
     }
 
     pub fn get_neighbours(&self, x: usize, y: usize, z: usize) -> VoxelNeighbours {
         let mut neighbours = VoxelNeighbours{top:false, bottom: false, left: false, right: false, front: false, back: false};
-        if x > 1 && !matches!(self.voxels[Self::coordiantes_to_index(x-1, y, z)],VoxelMaterial::Air){
+        if x > 0 && self.get(x-1, y, z) != VoxelMaterial::AIR {
             neighbours.left = true;
         }
-        if x+1 < WORLD_SIZE && !matches!(self.voxels[Self::coordiantes_to_index(x+1, y, z)],VoxelMaterial::Air){
+        if x+1 < self.width && self.get(x+1, y, z) != VoxelMaterial::AIR {
             neighbours.right = true;
         }
-        if y > 1 && !matches!(self.voxels[Self::coordiantes_to_index(x, y-1, z)],VoxelMaterial::Air){
+        if y > 0 && self.get(x, y-1, z) != VoxelMaterial::AIR {
             neighbours.bottom = true;
         }
-        if y+1 < WORLD_SIZE && !matches!(self.voxels[Self::coordiantes_to_index(x, y+1, z)],VoxelMaterial::Air){
+        if y+1 < self.height && self.get(x, y+1, z) != VoxelMaterial::AIR {
             neighbours.top = true;
         }
 
-        if z > 1 && !matches!(self.voxels[Self::coordiantes_to_index(x, y, z-1)],VoxelMaterial::Air){
+        if z > 0 && self.get(x, y, z-1) != VoxelMaterial::AIR {
             neighbours.back = true;
         }
-        if z+1 < WORLD_SIZE && !matches!(self.voxels[Self::coordiantes_to_index(x, y, z+1)],VoxelMaterial::Air){
+        if z+1 < self.depth && self.get(x, y, z+1) != VoxelMaterial::AIR {
             neighbours.front = true;
         }
 
@@ -138,17 +420,218 @@ impl World {
     }
 }
 
+fn neighbour_chunks(x: usize, y: usize, z: usize, (chunks_x, chunks_y, chunks_z): (usize, usize, usize)) -> Vec<(usize, usize, usize)> {
+    let range = |position: usize, chunks: usize| position.saturating_sub(1) / CHUNK_SIZE..=((position + 1) / CHUNK_SIZE).min(chunks - 1);
+    let mut neighbours = Vec::with_capacity(8);
+    for chunk_y in range(y, chunks_y) {
+        for chunk_z in range(z, chunks_z) {
+            for chunk_x in range(x, chunks_x) {
+                neighbours.push((chunk_x, chunk_y, chunk_z));
+            }
+        }
+    }
+
+    neighbours
+}
+
+/// Returns whether a solid voxel was added or removed.
+fn set_in_slot(slot: &mut Option<Box<Chunk>>, material: VoxelMaterial, x: usize, y: usize, z: usize) -> bool {
+    if slot.is_none() {
+        if material == VoxelMaterial::AIR {
+            return false;
+        }
+        *slot = Some(Box::new(Chunk::new()));
+    }
+    let chunk = slot.as_mut().unwrap();
+    let previous = chunk.get(x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE);
+    chunk.set(material, x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE);
+    if chunk.is_empty() {
+        *slot = None;
+    }
+
+    previous != material && (previous.is_solid() || material.is_solid())
+}
+
+/// Exclusive view of one chunk and its neighbours, so regions that don't overlap can be simulated on separate threads.
+pub struct WorldRegion<'a>{
+    width: usize,
+    height: usize,
+    depth: usize,
+    chunk: (usize, usize, usize),
+    slots: [Option<&'a mut Option<Box<Chunk>>>; 27],
+    structure_changed: Vec<(usize, usize, usize)>
+}
+impl WorldRegion<'_> {
+    fn slot_index(offset_x: usize, offset_y: usize, offset_z: usize) -> usize {
+        offset_x + offset_z*3 + offset_y*9
+    }
+
+    fn slot(&self, x: usize, y: usize, z: usize) -> &Option<Box<Chunk>> {
+        self.slots[self.find_slot(x, y, z)].as_deref().expect("voxel is outside of the world")
+    }
+
+    fn slot_mut(&mut self, x: usize, y: usize, z: usize) -> &mut Option<Box<Chunk>> {
+        let index = self.find_slot(x, y, z);
+        self.slots[index].as_deref_mut().expect("voxel is outside of the world")
+    }
+
+    fn find_slot(&self, x: usize, y: usize, z: usize) -> usize {
+        let offset = |position: usize, chunk: usize| (position / CHUNK_SIZE + 1)
+            .checked_sub(chunk)
+            .filter(|i| *i < 3)
+            .expect("voxel is outside of the region");
+        Self::slot_index(offset(x, self.chunk.0), offset(y, self.chunk.1), offset(z, self.chunk.2))
+    }
+
+    pub fn get_chunk(&self) -> (usize, usize, usize) {
+        self.chunk
+    }
+
+    pub fn get_chunk_bounds(&self) -> ((usize, usize), (usize, usize), (usize, usize)) {
+        let x = self.chunk.0*CHUNK_SIZE;
+        let y = self.chunk.1*CHUNK_SIZE;
+        let z = self.chunk.2*CHUNK_SIZE;
+        (
+            (x, (x + CHUNK_SIZE).min(self.width)),
+            (y, (y + CHUNK_SIZE).min(self.height)),
+            (z, (z + CHUNK_SIZE).min(self.depth))
+        )
+    }
+
+    pub fn is_in_bounds(&self, x: usize, y: usize, z: usize) -> bool {
+        x < self.width && y < self.height && z < self.depth
+    }
+
+    /// Whether a voxel and all of its neighbours are covered by this region.
+    pub fn is_inside(&self, x: usize, y: usize, z: usize) -> bool {
+        let inside = |position: usize, chunk: usize| position.saturating_sub(1) / CHUNK_SIZE + 1 >= chunk && (position + 1) / CHUNK_SIZE <= chunk + 1;
+        self.is_in_bounds(x, y, z) && inside(x, self.chunk.0) && inside(y, self.chunk.1) && inside(z, self.chunk.2)
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> VoxelMaterial {
+        match self.slot(x, y, z) {
+            Some(chunk) => chunk.get(x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE),
+            None => VoxelMaterial::AIR,
+        }
+    }
+
+    pub fn set(&mut self, material: VoxelMaterial, x: usize, y: usize, z: usize) {
+        let chunk = (x / CHUNK_SIZE, y / CHUNK_SIZE, z / CHUNK_SIZE);
+        if set_in_slot(self.slot_mut(x, y, z), material, x, y, z) && !self.structure_changed.contains(&chunk) {
+            self.structure_changed.push(chunk);
+        }
+        self.wake(x, y, z);
+    }
+
+    /// Chunks in which a solid voxel was added or removed through this region.
+    pub fn get_structure_changed(&self) -> &[(usize, usize, usize)] {
+        &self.structure_changed
+    }
+
+    pub fn wake(&mut self, x: usize, y: usize, z: usize) {
+        let chunks = (self.width.div_ceil(CHUNK_SIZE), self.height.div_ceil(CHUNK_SIZE), self.depth.div_ceil(CHUNK_SIZE));
+        for (chunk_x, chunk_y, chunk_z) in neighbour_chunks(x, y, z, chunks) {
+            let index = self.find_slot(chunk_x*CHUNK_SIZE, chunk_y*CHUNK_SIZE, chunk_z*CHUNK_SIZE);
+            if let Some(Some(chunk)) = self.slots[index].as_deref_mut() {
+                chunk.wake();
+            }
+        }
+    }
+
+    pub fn place(&mut self, material: VoxelMaterial, x: usize, y: usize, z: usize) {
+        self.set(material, x, y, z);
+        self.set_temperature(MaterialProperties::new(&material).initial_temperature, x, y, z);
+        self.set_velocity(0.0, x, y, z);
+        self.set_age(0, x, y, z);
+    }
+
+    pub fn get_temperature(&self, x: usize, y: usize, z: usize) -> f32 {
+        match self.slot(x, y, z) {
+            Some(chunk) => chunk.get_temperature(x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE),
+            None => AMBIENT_TEMPERATURE,
+        }
+    }
+
+    pub fn set_temperature(&mut self, temperature: f32, x: usize, y: usize, z: usize) {
+        if let Some(chunk) = self.slot_mut(x, y, z) {
+            chunk.set_temperature(temperature, x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE);
+        }
+    }
+
+    pub fn get_velocity(&self, x: usize, y: usize, z: usize) -> f32 {
+        match self.slot(x, y, z) {
+            Some(chunk) => chunk.get_velocity(x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE),
+            None => 0.0,
+        }
+    }
+
+    pub fn set_velocity(&mut self, velocity: f32, x: usize, y: usize, z: usize) {
+        if let Some(chunk) = self.slot_mut(x, y, z) {
+            chunk.set_velocity(velocity, x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE);
+        }
+    }
+
+    pub fn is_moved(&self, x: usize, y: usize, z: usize) -> bool {
+        match self.slot(x, y, z) {
+            Some(chunk) => chunk.is_moved(x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE),
+            None => false,
+        }
+    }
+
+    pub fn set_moved(&mut self, x: usize, y: usize, z: usize) {
+        if let Some(chunk) = self.slot_mut(x, y, z) {
+            chunk.set_moved(x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE);
+        }
+    }
+
+    pub fn get_age(&self, x: usize, y: usize, z: usize) -> u16 {
+        match self.slot(x, y, z) {
+            Some(chunk) => chunk.get_age(x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE),
+            None => 0,
+        }
+    }
+
+    pub fn set_age(&mut self, age: u16, x: usize, y: usize, z: usize) {
+        if let Some(chunk) = self.slot_mut(x, y, z) {
+            chunk.set_age(age, x % CHUNK_SIZE, y % CHUNK_SIZE, z % CHUNK_SIZE);
+        }
+    }
+
+    pub fn swap(&mut self, x1: usize, y1: usize, z1: usize, x2: usize, y2: usize, z2: usize) {
+        let first = self.get(x1, y1, z1);
+        let first_temperature = self.get_temperature(x1, y1, z1);
+        let first_velocity = self.get_velocity(x1, y1, z1);
+        let first_age = self.get_age(x1, y1, z1);
+        let second = self.get(x2, y2, z2);
+        let second_temperature = self.get_temperature(x2, y2, z2);
+        let second_velocity = self.get_velocity(x2, y2, z2);
+        let second_age = self.get_age(x2, y2, z2);
+
+        self.set(first, x2, y2, z2);
+        self.set_temperature(first_temperature, x2, y2, z2);
+        self.set_velocity(first_velocity, x2, y2, z2);
+        self.set_age(first_age, x2, y2, z2);
+        self.set(second, x1, y1, z1);
+        self.set_temperature(second_temperature, x1, y1, z1);
+        self.set_velocity(second_velocity, x1, y1, z1);
+        self.set_age(second_age, x1, y1, z1);
+    }
+}
+
 #[cfg(test)]
 mod tests{
-    use super::{World, WORLD_SIZE};
+    use super::{VoxelMaterial, World};
+    use crate::chunk::CHUNK_SIZE;
 
     #[test]
     fn test() {
-        for x in 0..WORLD_SIZE {
-            for y in 0..WORLD_SIZE {
-                for z in 0..WORLD_SIZE {
-                    let index = World::coordiantes_to_index(x, y, z);
-                    let (x1, y1, z1) = World::index_to_coordinates(index);
+        let world = World::new(7, 5, 3);
+        for x in 0..world.get_width() {
+            for y in 0..world.get_height() {
+                for z in 0..world.get_depth() {
+                    let index = world.coordiantes_to_index(x, y, z);
+                    assert!(index < 7*5*3);
+                    let (x1, y1, z1) = world.index_to_coordinates(index);
                     assert_eq!(x, x1);
                     assert_eq!(y, y1);
                     assert_eq!(z, z1);
@@ -156,4 +639,73 @@ mod tests{
             }
         }
     }
+
+    #[test]
+    fn test_chunk_allocation() {
+        let mut world = World::new(CHUNK_SIZE*2+3, CHUNK_SIZE, CHUNK_SIZE+1);
+        assert_eq!(world.get_allocated_chunk_count(), 0);
+
+        world.set(VoxelMaterial::AIR, 0, 0, 0);
+        assert_eq!(world.get_allocated_chunk_count(), 0);
+
+        world.set(VoxelMaterial::from_name("Sand").unwrap(), CHUNK_SIZE*2+2, CHUNK_SIZE-1, CHUNK_SIZE);
+        world.set(VoxelMaterial::from_name("Water").unwrap(), CHUNK_SIZE*2+1, CHUNK_SIZE-1, CHUNK_SIZE);
+        assert_eq!(world.get_allocated_chunk_count(), 1);
+        assert_eq!(world.get(CHUNK_SIZE*2+2, CHUNK_SIZE-1, CHUNK_SIZE), VoxelMaterial::from_name("Sand").unwrap());
+        assert_eq!(world.get(CHUNK_SIZE*2+1, CHUNK_SIZE-1, CHUNK_SIZE), VoxelMaterial::from_name("Water").unwrap());
+        assert_eq!(world.get(CHUNK_SIZE*2, CHUNK_SIZE-1, CHUNK_SIZE), VoxelMaterial::AIR);
+        assert_eq!(world.get_occupied().len(), 2);
+
+        world.set(VoxelMaterial::AIR, CHUNK_SIZE*2+2, CHUNK_SIZE-1, CHUNK_SIZE);
+        assert_eq!(world.get_allocated_chunk_count(), 1);
+        world.set(VoxelMaterial::AIR, CHUNK_SIZE*2+1, CHUNK_SIZE-1, CHUNK_SIZE);
+        assert_eq!(world.get_allocated_chunk_count(), 0);
+    }
+
+    #[test]
+    fn test_split_regions() {
+        let mut world = World::new(CHUNK_SIZE*4, CHUNK_SIZE, CHUNK_SIZE);
+        world.set(VoxelMaterial::from_name("Sand").unwrap(), CHUNK_SIZE, 0, 0);
+        let mut regions = world.split_regions(&[(0, 0, 0), (3, 0, 0)]);
+        assert_eq!(regions[0].get(CHUNK_SIZE, 0, 0), VoxelMaterial::from_name("Sand").unwrap());
+        regions[0].swap(CHUNK_SIZE, 0, 0, CHUNK_SIZE-1, 0, 0);
+        regions[1].set(VoxelMaterial::from_name("Stone").unwrap(), CHUNK_SIZE*3-1, 0, 0);
+        assert!(!regions[0].is_moved(CHUNK_SIZE-1, 0, 0));
+        regions[0].set_moved(CHUNK_SIZE-1, 0, 0);
+        assert!(regions[0].is_moved(CHUNK_SIZE-1, 0, 0));
+
+        assert_eq!(world.get(CHUNK_SIZE-1, 0, 0), VoxelMaterial::from_name("Sand").unwrap());
+        assert_eq!(world.get(CHUNK_SIZE*3-1, 0, 0), VoxelMaterial::from_name("Stone").unwrap());
+        assert_eq!(world.get_allocated_chunks(), vec![(0, 0, 0), (2, 0, 0)]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_overlapping_regions() {
+        let mut world = World::new(CHUNK_SIZE*4, CHUNK_SIZE, CHUNK_SIZE);
+        world.split_regions(&[(0, 0, 0), (2, 0, 0)]);
+    }
+
+    #[test]
+    fn test_large_sparse_world() {
+        let mut world = World::new(512, 512, 512);
+        world.set(VoxelMaterial::from_name("Stone").unwrap(), 511, 511, 511);
+        world.set(VoxelMaterial::from_name("Stone").unwrap(), 0, 0, 0);
+        assert_eq!(world.get_allocated_chunk_count(), 2);
+        assert_eq!(world.get(511, 511, 511), VoxelMaterial::from_name("Stone").unwrap());
+        assert_eq!(world.get_index(world.coordiantes_to_index(0, 0, 0)), VoxelMaterial::from_name("Stone").unwrap());
+        assert_eq!(world.get_neighbours(511, 510, 511).get_neighbours_count(), 1);
+    }
+
+    #[test]
+    fn test_neighbours_at_the_edge() {
+        let mut world = World::new(3, 3, 3);
+        for (x, y, z) in [(0, 0, 0), (1, 0, 0), (0, 1, 0), (0, 0, 1)] {
+            world.set(VoxelMaterial::from_name("Sand").unwrap(), x, y, z);
+        }
+        assert!(world.get_neighbours(1, 0, 0).left);
+        assert!(world.get_neighbours(0, 1, 0).bottom);
+        assert!(world.get_neighbours(0, 0, 1).back);
+        assert_eq!(world.get_neighbours(0, 0, 0).get_neighbours_count(), 3);
+    }
 }
\ No newline at end of file
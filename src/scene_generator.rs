@@ -2,7 +2,9 @@ use std::{cell::RefCell, collections::HashSet, f32::consts::PI, rc::Rc};
 
 use kiss3d::{nalgebra::{Point2, Point3, Quaternion, Translation, Unit, UnitQuaternion, Vector3}, resource::Mesh, scene::SceneNode, window::Window};
 
-use crate::{model::{VoxelMaterial, VoxelNeighbours, World, WORLD_SIZE}, scene_map::SceneMap, texture_generator::TextureGenerator, wire_cube::WireCube};
+use sand_3d::{chunk::CHUNK_SIZE, model::{VoxelMaterial, VoxelNeighbours, World}};
+
+use crate::{scene_map::SceneMap, texture_generator::TextureGenerator, wire_cube::WireCube};
 
 pub const VOXEL_SIZE: f32 = 3.0;
 const BRIGHTNESS: f32 = 1.5;
@@ -25,7 +27,7 @@ pub struct SceneGenerator{
     mesh: Rc<RefCell<Mesh>>
 }
 impl SceneGenerator {
-    pub fn new(x: f32, y: f32, z: f32) -> Self {
+    pub fn new(x: f32, y: f32, z: f32, world: &World) -> Self {
         let texture_generator = TextureGenerator::new();
         let origin = Translation::from(Vector3::new(x, y, z));
         let left_rotation = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), -PI/2.0);
@@ -44,7 +46,9 @@ impl SceneGenerator {
             x - VOXEL_SIZE/2.0,
             y - VOXEL_SIZE/2.0,
             z - VOXEL_SIZE/2.0,
-            WORLD_SIZE as f32 * VOXEL_SIZE,
+            world.get_width() as f32 * VOXEL_SIZE,
+            world.get_height() as f32 * VOXEL_SIZE,
+            world.get_depth() as f32 * VOXEL_SIZE,
             0.0,
             0.0,
             0.0
@@ -137,10 +141,17 @@ impl SceneGenerator {
     }
 
     fn set_texutures(&self, nodes: &mut Vec<SceneNode>, material: &VoxelMaterial) {
+        // materials without a texture are drawn in their plain color
         let texture = self.texture_generator.get(material);
+        let (r, g, b) = material.get_color();
         for i in nodes {
-            i.set_texture(texture.clone());
-            i.set_color(BRIGHTNESS, BRIGHTNESS, BRIGHTNESS);
+            match &texture {
+                Some(texture) => {
+                    i.set_texture(texture.clone());
+                    i.set_color(BRIGHTNESS, BRIGHTNESS, BRIGHTNESS);
+                },
+                None => i.set_color(r as f32/255.0, g as f32/255.0, b as f32/255.0),
+            }
         }
     }
 
@@ -168,8 +179,8 @@ impl SceneGenerator {
 
     pub fn generate_scene(&self, window: &mut Window, world: &World, scene_map: &mut SceneMap, changed: HashSet<usize>) {
         for i in changed {
-            let (x,y,z) = World::index_to_coordinates(i);
-            if matches!(world.get_index(i), VoxelMaterial::Air) {
+            let (x,y,z) = world.index_to_coordinates(i);
+            if world.get_index(i) == VoxelMaterial::AIR {
                 continue;
             }
             scene_map.add_mesh(x, y, z, self.generate_nodes(window, world, x, y, z));
@@ -179,5 +190,22 @@ impl SceneGenerator {
     pub fn draw_border(&self, window: &mut Window) {
         self.frame_border.draw(window);
     }
+
+    pub fn draw_active_chunks(&self, window: &mut Window, world: &World) {
+        let chunk_size = CHUNK_SIZE as f32 * VOXEL_SIZE;
+        for (chunk_x, chunk_y, chunk_z) in world.get_active_chunks() {
+            let ((min_x, max_x), (min_y, max_y), (min_z, max_z)) = world.get_chunk_bounds(chunk_x, chunk_y, chunk_z);
+            let wire_cube = WireCube::new(
+                self.origin.x + chunk_x as f32 * chunk_size - VOXEL_SIZE/2.0,
+                self.origin.y + chunk_y as f32 * chunk_size - VOXEL_SIZE/2.0,
+                self.origin.z + chunk_z as f32 * chunk_size - VOXEL_SIZE/2.0,
+                (max_x - min_x) as f32 * VOXEL_SIZE,
+                (max_y - min_y) as f32 * VOXEL_SIZE,
+                (max_z - min_z) as f32 * VOXEL_SIZE,
+                0.1, 0.8, 0.1
+            );
+            wire_cube.draw(window);
+        }
+    }
 }
 
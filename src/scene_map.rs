@@ -1,34 +1,48 @@
-use std::{collections::{HashMap, HashSet}, mem::replace};
+use std::{collections::{HashMap, HashSet}, mem::take};
 
 use kiss3d::{scene::SceneNode, window::Window};
 
-use crate::model::{World, WORLD_SIZE};
+use sand_3d::{model::World, voxel_simulator::ChangeListener};
 
 
 pub struct SceneMap{
+    width: usize,
+    height: usize,
+    depth: usize,
     scene_nodes: HashMap<usize, Vec<SceneNode>>,
     to_update: HashSet<usize>
 }
 impl SceneMap{
-    pub fn new() -> Self {
+    pub fn new(world: &World) -> Self {
         let scene_nodes = HashMap::new();
-        let mut to_update = HashSet::new();
-        for y in 0..WORLD_SIZE {
-            for z in 0..WORLD_SIZE {
-                for x in 0..WORLD_SIZE {
-                    to_update.insert(World::coordiantes_to_index(x, y, z));
-                }    
-            }
-        }
+        let to_update = world.get_occupied()
+            .into_iter()
+            .map(|(x, y, z)| world.coordiantes_to_index(x, y, z))
+            .collect();
 
         Self {
+            width: world.get_width(),
+            height: world.get_height(),
+            depth: world.get_depth(),
             scene_nodes,
             to_update
         }
     }
 
+    fn coordiantes_to_index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + z*self.width + y*self.width*self.depth
+    }
+
+    fn insert(&mut self, x: usize, y: usize, z: usize) {
+        if x < self.width && y < self.height && z < self.depth {
+            let index = self.coordiantes_to_index(x, y, z);
+            self.to_update.insert(index);
+        }
+    }
+
     pub fn add_mesh(&mut self, x: usize, y:usize, z:usize, nodes: Vec<SceneNode>) {
-        self.scene_nodes.insert(World::coordiantes_to_index(x, y, z), nodes);
+        let index = self.coordiantes_to_index(x, y, z);
+        self.scene_nodes.insert(index, nodes);
     }
 
     pub fn get_and_remove_changed(&mut self, window: &mut Window) -> HashSet<usize> {
@@ -42,30 +56,37 @@ impl SceneMap{
                 window.remove_node(j);
             }
         }
-        replace(&mut self.to_update, HashSet::new()) 
+        take(&mut self.to_update)
     }
 
-    pub fn update(&mut self, x: usize, y:usize, z:usize) {
-        if x < WORLD_SIZE && y < WORLD_SIZE && z < WORLD_SIZE {
-            self.to_update.insert(World::coordiantes_to_index(x, y, z));
-        }
-        if x >= 1 && x-1 < WORLD_SIZE && y < WORLD_SIZE && z < WORLD_SIZE {
-            self.to_update.insert(World::coordiantes_to_index(x-1, y, z));
-        }
-        if x < WORLD_SIZE && y >= 1 && y-1 < WORLD_SIZE && z < WORLD_SIZE {
-            self.to_update.insert(World::coordiantes_to_index(x, y-1, z));
-        }
-        if x < WORLD_SIZE && y < WORLD_SIZE && z >= 1 && z - 1 < WORLD_SIZE {
-            self.to_update.insert(World::coordiantes_to_index(x, y, z-1));
+    pub fn clear(&mut self, window: &mut Window) {
+        for nodes in self.scene_nodes.values_mut() {
+            for i in nodes {
+                window.remove_node(i);
+            }
         }
-        if x+1 < WORLD_SIZE && y < WORLD_SIZE && z < WORLD_SIZE {
-            self.to_update.insert(World::coordiantes_to_index(x+1, y, z));
+        self.scene_nodes.clear();
+        self.to_update.clear();
+    }
+
+    pub fn update(&mut self, x: usize, y:usize, z:usize) {
+        self.insert(x, y, z);
+        if x >= 1 {
+            self.insert(x-1, y, z);
         }
-        if x < WORLD_SIZE && y+1 < WORLD_SIZE && z < WORLD_SIZE {
-            self.to_update.insert(World::coordiantes_to_index(x, y+1, z));
+        if y >= 1 {
+            self.insert(x, y-1, z);
         }
-        if  x < WORLD_SIZE && y < WORLD_SIZE && z + 1 < WORLD_SIZE {
-            self.to_update.insert(World::coordiantes_to_index(x, y, z+1));
+        if z >= 1 {
+            self.insert(x, y, z-1);
         }
+        self.insert(x+1, y, z);
+        self.insert(x, y+1, z);
+        self.insert(x, y, z+1);
+    }
+}
+impl ChangeListener for SceneMap {
+    fn voxel_changed(&mut self, x: usize, y: usize, z: usize) {
+        self.update(x, y, z);
     }
 }
\ No newline at end of file
@@ -0,0 +1,454 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{chunk::CHUNK_SIZE, material_properties::{MaterialProperties, MaterialType}, model::{VoxelMaterial, World}, voxel_simulator::{ChangeListener, GRAVITY, TERMINAL_VELOCITY}};
+
+type Position = (usize, usize, usize);
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Down, Up
+}
+
+/// Keeps solids in place only while they are held up. A solid is supported when it rests on the floor or on a
+/// supported solid, or when it hangs at most its material's strength in voxels sideways or below one. Unsupported
+/// solids move as rigid clusters of connected voxels: they fall or rise through powders and liquids by their
+/// average weight, and break into their rubble powder instead of falling if they have one.
+pub struct StructureSimulator{
+    face_neighbours: [(isize, isize, isize); 6],
+    // chunks of unsupported clusters that could not move, looked at again once something wakes them
+    waiting: HashSet<Position>,
+    visited_voxels: usize
+}
+impl Default for StructureSimulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl StructureSimulator {
+    pub fn new() -> Self {
+        Self { face_neighbours: [(0, 1, 0), (1, 0, 0), (-1, 0, 0), (0, 0, 1), (0, 0, -1), (0, -1, 0)], waiting: HashSet::new(), visited_voxels: 0 }
+    }
+
+    /// Voxels looked at while searching for solids in the last step.
+    pub fn get_visited_voxel_count(&self) -> usize {
+        self.visited_voxels
+    }
+
+    /// Solids in the changed chunks and the chunks around them, together with every solid connected to those.
+    /// Whether a solid is supported only depends on the solids it is connected to, so the others are left out.
+    fn find_solids(&mut self, world: &World, changed: &[Position]) -> Vec<Position> {
+        let mut found = HashSet::new();
+        let mut queue = VecDeque::new();
+        for (chunk_x, chunk_y, chunk_z) in world.get_allocated_chunks_around(changed) {
+            let ((min_x, max_x), (min_y, max_y), (min_z, max_z)) = world.get_chunk_bounds(chunk_x, chunk_y, chunk_z);
+            for y in min_y..max_y {
+                for z in min_z..max_z {
+                    for x in min_x..max_x {
+                        self.visited_voxels += 1;
+                        if world.get(x, y, z).is_solid() && found.insert((x, y, z)) {
+                            queue.push_back((x, y, z));
+                        }
+                    }
+                }
+            }
+        }
+
+        while let Some((x, y, z)) = queue.pop_front() {
+            for (dx, dy, dz) in self.face_neighbours {
+                let (Some(other_x), Some(other_y), Some(other_z)) = (x.checked_add_signed(dx), y.checked_add_signed(dy), z.checked_add_signed(dz)) else {
+                    continue;
+                };
+                if !world.is_in_bounds(other_x, other_y, other_z) {
+                    continue;
+                }
+                self.visited_voxels += 1;
+                if world.get(other_x, other_y, other_z).is_solid() && found.insert((other_x, other_y, other_z)) {
+                    queue.push_back((other_x, other_y, other_z));
+                }
+            }
+        }
+        let mut solids: Vec<Position> = found.into_iter().collect();
+        solids.sort_by_key(|(x, y, z)| (*y, *z, *x));
+
+        solids
+    }
+
+    /// Distance of every supported solid from the voxels it rests on, resting costs nothing and every step
+    /// sideways or down costs one.
+    fn find_supported(&self, world: &World, solids: &[Position]) -> HashMap<Position, u32> {
+        let strengths: HashMap<Position, u32> = solids.iter()
+            .map(|(x, y, z)| ((*x, *y, *z), MaterialProperties::new(&world.get(*x, *y, *z)).strength))
+            .collect();
+        let mut distances = HashMap::new();
+        let mut queue = VecDeque::new();
+        for position in solids.iter().take_while(|(_, y, _)| *y == 0) {
+            distances.insert(*position, 0);
+            queue.push_back(*position);
+        }
+
+        while let Some((x, y, z)) = queue.pop_front() {
+            let distance = distances[&(x, y, z)];
+            for (dx, dy, dz) in self.face_neighbours {
+                let (Some(other_x), Some(other_y), Some(other_z)) = (x.checked_add_signed(dx), y.checked_add_signed(dy), z.checked_add_signed(dz)) else {
+                    continue;
+                };
+                let position = (other_x, other_y, other_z);
+                let Some(strength) = strengths.get(&position) else {
+                    continue;
+                };
+                let other_distance = if dy == 1 { distance } else { distance + 1 };
+                if other_distance > *strength || distances.get(&position).is_some_and(|i| *i <= other_distance) {
+                    continue;
+                }
+                distances.insert(position, other_distance);
+                if dy == 1 {
+                    queue.push_front(position);
+                }else{
+                    queue.push_back(position);
+                }
+            }
+        }
+
+        distances
+    }
+
+    /// Groups unsupported solids into clusters of face connected voxels, each sorted bottom first.
+    fn find_clusters(&self, unsupported: &[Position]) -> Vec<Vec<Position>> {
+        let remaining: HashSet<Position> = unsupported.iter().copied().collect();
+        let mut visited = HashSet::new();
+        let mut clusters = vec![];
+        for start in unsupported {
+            if !visited.insert(*start) {
+                continue;
+            }
+            let mut cluster = vec![*start];
+            let mut queue = VecDeque::from([*start]);
+            while let Some((x, y, z)) = queue.pop_front() {
+                for (dx, dy, dz) in self.face_neighbours {
+                    let (Some(other_x), Some(other_y), Some(other_z)) = (x.checked_add_signed(dx), y.checked_add_signed(dy), z.checked_add_signed(dz)) else {
+                        continue;
+                    };
+                    let position = (other_x, other_y, other_z);
+                    if remaining.contains(&position) && visited.insert(position) {
+                        cluster.push(position);
+                        queue.push_back(position);
+                    }
+                }
+            }
+            cluster.sort_by_key(|(x, y, z)| (*y, *z, *x));
+            clusters.push(cluster);
+        }
+
+        clusters
+    }
+
+    fn get_weight(world: &World, cluster: &[Position]) -> f32 {
+        cluster.iter().map(|(x, y, z)| MaterialProperties::new(&world.get(*x, *y, *z)).weight as f32).sum::<f32>() / cluster.len() as f32
+    }
+
+    /// A cluster sinks when every voxel under it is part of it or a powder, liquid or gas lighter than the
+    /// cluster on average, and rises when every voxel above it is part of it or a heavier liquid.
+    fn can_move(world: &World, cluster: &[Position], direction: Direction) -> bool {
+        let positions: HashSet<&Position> = cluster.iter().collect();
+        let weight = Self::get_weight(world, cluster);
+        cluster.iter().all(|(x, y, z)| {
+            let other_y = match direction {
+                Direction::Down if *y == 0 => return false,
+                Direction::Down => y - 1,
+                Direction::Up if *y + 1 == world.get_height() => return false,
+                Direction::Up => y + 1,
+            };
+            if positions.contains(&(*x, other_y, *z)) {
+                return true;
+            }
+            let other = MaterialProperties::new(&world.get(*x, other_y, *z));
+            match direction {
+                Direction::Down => !matches!(other.material_type, MaterialType::SOLID) && (other.weight as f32) < weight,
+                Direction::Up => matches!(other.material_type, MaterialType::LIQUID) && other.weight as f32 > weight,
+            }
+        })
+    }
+
+    /// Moves the whole cluster by one voxel, whatever it displaced ends up on the other side of the same column.
+    fn move_cluster(world: &mut World, listener: &mut dyn ChangeListener, cluster: &mut [Position], direction: Direction) {
+        let mut order: Vec<&mut Position> = cluster.iter_mut().collect();
+        if matches!(direction, Direction::Up) {
+            order.reverse();
+        }
+        for (x, y, z) in order {
+            let other_y = match direction {
+                Direction::Down => *y - 1,
+                Direction::Up => *y + 1,
+            };
+            world.swap(*x, *y, *z, *x, other_y, *z);
+            listener.voxel_changed(*x, *y, *z);
+            listener.voxel_changed(*x, other_y, *z);
+            *y = other_y;
+        }
+    }
+
+    /// Falls with the same gravity as powders and liquids, the velocity is shared by the whole cluster.
+    fn fall(world: &mut World, listener: &mut dyn ChangeListener, mut cluster: Vec<Position>) {
+        let (x, y, z) = cluster[0];
+        let velocity = (world.get_velocity(x, y, z) - GRAVITY).max(-TERMINAL_VELOCITY);
+        let distance = (-velocity as usize).max(1);
+        let mut fallen = 0;
+        while fallen < distance && Self::can_move(world, &cluster, Direction::Down) {
+            Self::move_cluster(world, listener, &mut cluster, Direction::Down);
+            fallen += 1;
+        }
+
+        let velocity = if fallen == distance { velocity } else { 0.0 };
+        for (x, y, z) in cluster {
+            world.set_velocity(velocity, x, y, z);
+        }
+    }
+
+    /// Looks at the chunks in which a solid was added or removed since the last step, and at unsupported solids
+    /// resting on powders or liquids once a voxel next to them changed and woke their chunk.
+    pub fn next_step(&mut self, world: &mut World, listener: &mut dyn ChangeListener) {
+        self.visited_voxels = 0;
+        if !self.waiting.is_empty() {
+            for (chunk_x, chunk_y, chunk_z) in world.get_active_chunks() {
+                if self.waiting.remove(&(chunk_x, chunk_y, chunk_z)) {
+                    world.mark_structure_changed(chunk_x, chunk_y, chunk_z);
+                }
+            }
+        }
+        let changed: Vec<Position> = world.take_structure_changed().into_iter().collect();
+        if changed.is_empty() {
+            return;
+        }
+
+        let solids = self.find_solids(world, &changed);
+        let supported = self.find_supported(world, &solids);
+        let unsupported: Vec<Position> = solids.into_iter().filter(|i| !supported.contains_key(i)).collect();
+        // lowest clusters first, so stacked clusters don't block each other
+        for mut cluster in self.find_clusters(&unsupported) {
+            if Self::can_move(world, &cluster, Direction::Down) {
+                let rubble: Vec<(Position, VoxelMaterial)> = cluster.iter()
+                    .filter_map(|(x, y, z)| MaterialProperties::new(&world.get(*x, *y, *z)).rubble.map(|i| ((*x, *y, *z), i)))
+                    .collect();
+                if rubble.is_empty() {
+                    Self::fall(world, listener, cluster);
+                }
+                // a breaking cluster leaves the voxels without rubble to fall on their own
+                for ((x, y, z), material) in rubble {
+                    world.set(material, x, y, z);
+                    listener.voxel_changed(x, y, z);
+                }
+            }else if Self::can_move(world, &cluster, Direction::Up) {
+                Self::move_cluster(world, listener, &mut cluster, Direction::Up);
+                for (x, y, z) in cluster {
+                    world.set_velocity(0.0, x, y, z);
+                }
+            }else{
+                self.waiting.extend(cluster.iter().map(|(x, y, z)| (x / CHUNK_SIZE, y / CHUNK_SIZE, z / CHUNK_SIZE)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::StructureSimulator;
+    use crate::model::{VoxelMaterial, World};
+
+    fn build_bridge(world: &mut World, material: VoxelMaterial, span: usize) {
+        for y in 0..4 {
+            world.place(material, 0, y, 0);
+            world.place(material, span + 1, y, 0);
+        }
+        for x in 1..=span {
+            world.place(material, x, 3, 0);
+        }
+    }
+
+    #[test]
+    fn test_bridges() {
+        let metal = VoxelMaterial::from_name("Metal").unwrap();
+        let mut short = World::new(22, 5, 1);
+        build_bridge(&mut short, metal, 16);
+        let mut long = World::new(22, 5, 1);
+        build_bridge(&mut long, metal, 20);
+        let mut simulator = StructureSimulator::new();
+
+        for _ in 0..5 {
+            simulator.next_step(&mut short, &mut ());
+            simulator.next_step(&mut long, &mut ());
+        }
+
+        // metal reaches 8 voxels from each pillar
+        assert!((1..=16).all(|x| short.get(x, 3, 0) == metal));
+        assert!((1..=8).chain(13..=20).all(|x| long.get(x, 3, 0) == metal));
+        assert!((9..=12).all(|x| long.get(x, 0, 0) == metal && long.get(x, 3, 0) == VoxelMaterial::AIR));
+    }
+
+    #[test]
+    fn test_overhang() {
+        let wood = VoxelMaterial::from_name("Wood").unwrap();
+        let water = VoxelMaterial::from_name("Water").unwrap();
+        let mut world = World::new(8, 6, 1);
+        for y in 0..5 {
+            world.place(wood, 0, y, 0);
+        }
+        for x in 1..8 {
+            world.place(wood, x, 4, 0);
+        }
+        world.place(water, 6, 0, 0);
+        let mut simulator = StructureSimulator::new();
+
+        for _ in 0..5 {
+            simulator.next_step(&mut world, &mut ());
+        }
+
+        assert!((1..=4).all(|x| world.get(x, 4, 0) == wood));
+        // the broken off piece lands on the heavier water
+        assert!((5..8).all(|x| world.get(x, 1, 0) == wood));
+        assert_eq!(world.get(6, 0, 0), water);
+
+        // cutting the pillar drops everything above the cut
+        world.set(VoxelMaterial::AIR, 0, 1, 0);
+        simulator.next_step(&mut world, &mut ());
+        assert_eq!(world.get(0, 1, 0), wood);
+        assert_eq!(world.get(4, 3, 0), wood);
+    }
+
+    #[test]
+    fn test_unsupported_ice_collapses() {
+        let ice = VoxelMaterial::from_name("Ice").unwrap();
+        let mut world = World::new(3, 4, 1);
+        world.place(ice, 0, 0, 0);
+        world.place(ice, 0, 1, 0);
+        world.place(ice, 1, 1, 0);
+        world.place(ice, 2, 3, 0);
+        let mut simulator = StructureSimulator::new();
+
+        simulator.next_step(&mut world, &mut ());
+
+        assert_eq!(world.get(1, 1, 0), ice);
+        assert_eq!(world.get(2, 3, 0), VoxelMaterial::from_name("Snow").unwrap());
+        assert_eq!(world.get_temperature(2, 3, 0), -10.0);
+    }
+
+    #[test]
+    fn test_cut_cluster_falls_rigidly() {
+        let metal = VoxelMaterial::from_name("Metal").unwrap();
+        let mut world = World::new(6, 40, 1);
+        for y in 0..40 {
+            world.place(metal, 0, y, 0);
+        }
+        // an L shape hanging from the top of the pillar
+        for x in 1..5 {
+            world.place(metal, x, 39, 0);
+        }
+        for y in 35..39 {
+            world.place(metal, 4, y, 0);
+        }
+        let mut simulator = StructureSimulator::new();
+        simulator.next_step(&mut world, &mut ());
+        assert_eq!(world.get(4, 35, 0), metal);
+
+        world.set(VoxelMaterial::AIR, 1, 39, 0);
+        for _ in 0..8 {
+            simulator.next_step(&mut world, &mut ());
+        }
+
+        // accelerating, the cluster fell further than one voxel per step and kept its shape
+        let top = (0..40).find(|y| world.get(2, *y, 0) == metal).unwrap();
+        assert!(top < 39 - 8, "fell only {} voxels", 39 - top);
+        assert!((2..5).all(|x| world.get(x, top, 0) == metal));
+        assert!((top-4..top).all(|y| world.get(4, y, 0) == metal));
+        assert_eq!(world.get_material_counts().into_iter().find(|(i, _)| *i == metal).unwrap().1, 40 + 3 + 4);
+    }
+
+    #[test]
+    fn test_falling_cluster_displaces_liquid() {
+        let metal = VoxelMaterial::from_name("Metal").unwrap();
+        let water = VoxelMaterial::from_name("Water").unwrap();
+        let mut world = World::new(3, 10, 1);
+        for x in 0..3 {
+            world.place(water, x, 0, 0);
+            world.place(water, x, 1, 0);
+            world.place(metal, x, 6, 0);
+        }
+        let mut simulator = StructureSimulator::new();
+
+        for _ in 0..10 {
+            simulator.next_step(&mut world, &mut ());
+        }
+
+        assert!((0..3).all(|x| world.get(x, 0, 0) == metal));
+        assert!((0..3).all(|x| world.get(x, 1, 0) == water && world.get(x, 2, 0) == water));
+        assert_eq!(world.get_velocity(1, 0, 0), 0.0);
+    }
+
+    #[test]
+    fn test_resting_cluster_waits_for_a_change() {
+        let sand = VoxelMaterial::from_name("Sand").unwrap();
+        let wood = VoxelMaterial::from_name("Wood").unwrap();
+        let mut world = World::new(4, 3, 1);
+        for x in 0..4 {
+            world.place(sand, x, 0, 0);
+            world.place(wood, x, 1, 0);
+        }
+        let mut simulator = StructureSimulator::new();
+
+        simulator.next_step(&mut world, &mut ());
+        assert!(simulator.get_visited_voxel_count() > 0);
+        world.begin_tick();
+        for _ in 0..3 {
+            simulator.next_step(&mut world, &mut ());
+            assert_eq!(simulator.get_visited_voxel_count(), 0);
+        }
+
+        world.set(VoxelMaterial::AIR, 0, 0, 0);
+        simulator.next_step(&mut world, &mut ());
+        assert!(simulator.get_visited_voxel_count() > 0);
+        assert!((0..4).all(|x| world.get(x, 1, 0) == wood));
+    }
+
+    #[test]
+    fn test_buoyancy() {
+        let water = VoxelMaterial::from_name("Water").unwrap();
+        let wood = VoxelMaterial::from_name("Wood").unwrap();
+        let metal = VoxelMaterial::from_name("Metal").unwrap();
+        for (material, expected_y) in [(wood, 7), (VoxelMaterial::from_name("Ice").unwrap(), 7), (metal, 0)] {
+            let mut world = World::new(2, 10, 1);
+            for y in 0..8 {
+                world.place(water, 0, y, 0);
+                world.place(water, 1, y, 0);
+            }
+            world.place(material, 0, 3, 0);
+            world.place(material, 1, 3, 0);
+            let mut simulator = StructureSimulator::new();
+
+            for _ in 0..10 {
+                simulator.next_step(&mut world, &mut ());
+            }
+
+            assert_eq!(world.get(0, expected_y, 0), material, "{}", material);
+            assert_eq!(world.get(1, expected_y, 0), material, "{}", material);
+            assert_eq!(world.get(0, 8, 0), VoxelMaterial::AIR);
+        }
+
+        // a raft carries as much metal as its average weight allows
+        for (metal_count, floats) in [(0, true), (1, false)] {
+            let mut world = World::new(4, 4, 1);
+            for x in 0..4 {
+                world.place(water, x, 0, 0);
+                world.place(wood, x, 1, 0);
+            }
+            for x in 0..metal_count {
+                world.place(metal, x, 2, 0);
+            }
+            let mut simulator = StructureSimulator::new();
+
+            for _ in 0..5 {
+                simulator.next_step(&mut world, &mut ());
+            }
+
+            assert_eq!(world.get(3, 0, 0) == water, floats);
+        }
+    }
+}
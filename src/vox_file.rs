@@ -0,0 +1,410 @@
+use std::{fmt::Display, fs::{self, File}, io::{self, BufReader, BufWriter, Read, Write}, path::Path};
+
+use crate::model::{VoxelMaterial, World};
+
+const MAGIC: &[u8; 4] = b"VOX ";
+const VERSION: i32 = 150;
+const MAX_MODEL_SIZE: usize = 256;
+const UNUSED_PALETTE_COLOR: [u8; 4] = [0x80, 0x80, 0x80, 0xff];
+
+/// MagicaVoxel's default palette as 0xAABBGGRR, indexed by color index, used when a file has no RGBA chunk.
+const DEFAULT_PALETTE: [u32; 256] = [
+    0x00000000, 0xffffffff, 0xffccffff, 0xff99ffff, 0xff66ffff, 0xff33ffff, 0xff00ffff, 0xffffccff,
+    0xffccccff, 0xff99ccff, 0xff66ccff, 0xff33ccff, 0xff00ccff, 0xffff99ff, 0xffcc99ff, 0xff9999ff,
+    0xff6699ff, 0xff3399ff, 0xff0099ff, 0xffff66ff, 0xffcc66ff, 0xff9966ff, 0xff6666ff, 0xff3366ff,
+    0xff0066ff, 0xffff33ff, 0xffcc33ff, 0xff9933ff, 0xff6633ff, 0xff3333ff, 0xff0033ff, 0xffff00ff,
+    0xffcc00ff, 0xff9900ff, 0xff6600ff, 0xff3300ff, 0xff0000ff, 0xffffffcc, 0xffccffcc, 0xff99ffcc,
+    0xff66ffcc, 0xff33ffcc, 0xff00ffcc, 0xffffcccc, 0xffcccccc, 0xff99cccc, 0xff66cccc, 0xff33cccc,
+    0xff00cccc, 0xffff99cc, 0xffcc99cc, 0xff9999cc, 0xff6699cc, 0xff3399cc, 0xff0099cc, 0xffff66cc,
+    0xffcc66cc, 0xff9966cc, 0xff6666cc, 0xff3366cc, 0xff0066cc, 0xffff33cc, 0xffcc33cc, 0xff9933cc,
+    0xff6633cc, 0xff3333cc, 0xff0033cc, 0xffff00cc, 0xffcc00cc, 0xff9900cc, 0xff6600cc, 0xff3300cc,
+    0xff0000cc, 0xffffff99, 0xffccff99, 0xff99ff99, 0xff66ff99, 0xff33ff99, 0xff00ff99, 0xffffcc99,
+    0xffcccc99, 0xff99cc99, 0xff66cc99, 0xff33cc99, 0xff00cc99, 0xffff9999, 0xffcc9999, 0xff999999,
+    0xff669999, 0xff339999, 0xff009999, 0xffff6699, 0xffcc6699, 0xff996699, 0xff666699, 0xff336699,
+    0xff006699, 0xffff3399, 0xffcc3399, 0xff993399, 0xff663399, 0xff333399, 0xff003399, 0xffff0099,
+    0xffcc0099, 0xff990099, 0xff660099, 0xff330099, 0xff000099, 0xffffff66, 0xffccff66, 0xff99ff66,
+    0xff66ff66, 0xff33ff66, 0xff00ff66, 0xffffcc66, 0xffcccc66, 0xff99cc66, 0xff66cc66, 0xff33cc66,
+    0xff00cc66, 0xffff9966, 0xffcc9966, 0xff999966, 0xff669966, 0xff339966, 0xff009966, 0xffff6666,
+    0xffcc6666, 0xff996666, 0xff666666, 0xff336666, 0xff006666, 0xffff3366, 0xffcc3366, 0xff993366,
+    0xff663366, 0xff333366, 0xff003366, 0xffff0066, 0xffcc0066, 0xff990066, 0xff660066, 0xff330066,
+    0xff000066, 0xffffff33, 0xffccff33, 0xff99ff33, 0xff66ff33, 0xff33ff33, 0xff00ff33, 0xffffcc33,
+    0xffcccc33, 0xff99cc33, 0xff66cc33, 0xff33cc33, 0xff00cc33, 0xffff9933, 0xffcc9933, 0xff999933,
+    0xff669933, 0xff339933, 0xff009933, 0xffff6633, 0xffcc6633, 0xff996633, 0xff666633, 0xff336633,
+    0xff006633, 0xffff3333, 0xffcc3333, 0xff993333, 0xff663333, 0xff333333, 0xff003333, 0xffff0033,
+    0xffcc0033, 0xff990033, 0xff660033, 0xff330033, 0xff000033, 0xffffff00, 0xffccff00, 0xff99ff00,
+    0xff66ff00, 0xff33ff00, 0xff00ff00, 0xffffcc00, 0xffcccc00, 0xff99cc00, 0xff66cc00, 0xff33cc00,
+    0xff00cc00, 0xffff9900, 0xffcc9900, 0xff999900, 0xff669900, 0xff339900, 0xff009900, 0xffff6600,
+    0xffcc6600, 0xff996600, 0xff666600, 0xff336600, 0xff006600, 0xffff3300, 0xffcc3300, 0xff993300,
+    0xff663300, 0xff333300, 0xff003300, 0xffff0000, 0xffcc0000, 0xff990000, 0xff660000, 0xff330000,
+    0xff0000ee, 0xff0000dd, 0xff0000bb, 0xff0000aa, 0xff000088, 0xff000077, 0xff000055, 0xff000044,
+    0xff000022, 0xff000011, 0xff00ee00, 0xff00dd00, 0xff00bb00, 0xff00aa00, 0xff008800, 0xff007700,
+    0xff005500, 0xff004400, 0xff002200, 0xff001100, 0xffee0000, 0xffdd0000, 0xffbb0000, 0xffaa0000,
+    0xff880000, 0xff770000, 0xff550000, 0xff440000, 0xff220000, 0xff110000, 0xffeeeeee, 0xffdddddd,
+    0xffbbbbbb, 0xffaaaaaa, 0xff888888, 0xff777777, 0xff555555, 0xff444444, 0xff222222, 0xff111111,
+];
+
+#[derive(Debug)]
+pub enum VoxFileError {
+    Io(io::Error),
+    InvalidMagic,
+    MissingChunk(&'static str),
+    InvalidData(String),
+    WorldTooLarge(usize, usize, usize),
+}
+impl Display for VoxFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::InvalidMagic => write!(f, "Not a MagicaVoxel file"),
+            Self::MissingChunk(id) => write!(f, "Missing {} chunk", id),
+            Self::InvalidData(message) => write!(f, "Invalid .vox file: {}", message),
+            Self::WorldTooLarge(width, height, depth) => write!(
+                f,
+                "World {}x{}x{} exceeds the .vox model limit of {}",
+                width, height, depth, MAX_MODEL_SIZE
+            ),
+        }
+    }
+}
+impl From<io::Error> for VoxFileError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+pub struct VoxMaterialMapping{
+    entries: Vec<((u8, u8, u8), VoxelMaterial)>
+}
+impl Default for VoxMaterialMapping {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl VoxMaterialMapping {
+    pub fn new() -> Self {
+        let entries = VoxelMaterial::get_all().into_iter()
+            .filter(|i| *i != VoxelMaterial::AIR)
+            .map(|i| (i.get_color(), i))
+            .collect();
+
+        Self { entries }
+    }
+
+    pub fn parse(source: &str) -> Result<Self, VoxFileError> {
+        let mut entries = vec![];
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid_line = || VoxFileError::InvalidData(format!("mapping line {}: '{}'", number + 1, line));
+            let (color, name) = line.split_once(char::is_whitespace).ok_or_else(invalid_line)?;
+            let color = u32::from_str_radix(color, 16).ok()
+                .filter(|_| color.len() == 6)
+                .ok_or_else(invalid_line)?;
+            let material = VoxelMaterial::from_name(name.trim()).ok_or_else(invalid_line)?;
+            entries.push((((color >> 16) as u8, (color >> 8) as u8, color as u8), material));
+        }
+        if entries.is_empty() {
+            return Err(VoxFileError::InvalidData("mapping is empty".to_string()));
+        }
+
+        Ok(Self { entries })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, VoxFileError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn get_material(&self, color: (u8, u8, u8)) -> VoxelMaterial {
+        let distance = |other: (u8, u8, u8)| {
+            let r = color.0 as i32 - other.0 as i32;
+            let g = color.1 as i32 - other.1 as i32;
+            let b = color.2 as i32 - other.2 as i32;
+            r*r + g*g + b*b
+        };
+
+        self.entries.iter()
+            .min_by_key(|(other, _)| distance(*other))
+            .map(|(_, material)| *material)
+            .unwrap()
+    }
+}
+
+fn read_i32(reader: &mut impl Read) -> Result<i32, VoxFileError> {
+    let mut buffer = [0; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(i32::from_le_bytes(buffer))
+}
+
+/// Reads `size` bytes without trusting the size enough to allocate it up front.
+fn read_bounded(reader: &mut impl Read, size: usize) -> Result<Vec<u8>, VoxFileError> {
+    let mut content = vec![];
+    reader.by_ref().take(size as u64).read_to_end(&mut content)?;
+    if content.len() != size {
+        return Err(VoxFileError::InvalidData("chunk exceeds file".to_string()));
+    }
+    Ok(content)
+}
+
+fn read_chunk(reader: &mut impl Read) -> Result<([u8; 4], Vec<u8>, usize), VoxFileError> {
+    let mut id = [0; 4];
+    reader.read_exact(&mut id)?;
+    let content_size = read_i32(reader)?;
+    let children_size = read_i32(reader)?;
+    if content_size < 0 || children_size < 0 {
+        return Err(VoxFileError::InvalidData("negative chunk size".to_string()));
+    }
+    let content = read_bounded(reader, content_size as usize)?;
+
+    Ok((id, content, children_size as usize))
+}
+
+fn content_i32(content: &[u8], offset: usize) -> Result<i32, VoxFileError> {
+    content.get(offset..offset+4)
+        .map(|i| i32::from_le_bytes([i[0], i[1], i[2], i[3]]))
+        .ok_or(VoxFileError::InvalidData("chunk too short".to_string()))
+}
+
+pub fn import(reader: &mut impl Read, mapping: &VoxMaterialMapping) -> Result<World, VoxFileError> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(VoxFileError::InvalidMagic);
+    }
+    read_i32(reader)?;
+
+    let (id, _, children_size) = read_chunk(reader)?;
+    if &id != b"MAIN" {
+        return Err(VoxFileError::MissingChunk("MAIN"));
+    }
+    let children = read_bounded(reader, children_size)?;
+    let mut children = children.as_slice();
+
+    let mut size = None;
+    let mut voxels = None;
+    let mut palette = None;
+    while !children.is_empty() {
+        let (id, content, nested_size) = read_chunk(&mut children)?;
+        if nested_size > children.len() {
+            return Err(VoxFileError::InvalidData("chunk children exceed file".to_string()));
+        }
+        children = &children[nested_size..];
+        match &id {
+            b"SIZE" if size.is_none() => size = Some((
+                content_i32(&content, 0)?,
+                content_i32(&content, 4)?,
+                content_i32(&content, 8)?
+            )),
+            b"XYZI" if voxels.is_none() => voxels = Some(content),
+            b"RGBA" => palette = Some(content),
+            _ => {},
+        }
+    }
+
+    let (size_x, size_y, size_z) = size.ok_or(VoxFileError::MissingChunk("SIZE"))?;
+    let voxels = voxels.ok_or(VoxFileError::MissingChunk("XYZI"))?;
+    // the RGBA chunk stores color index i at entry i-1
+    let palette = palette.unwrap_or_else(|| DEFAULT_PALETTE.iter()
+        .cycle()
+        .skip(1)
+        .take(256)
+        .flat_map(|i| i.to_le_bytes())
+        .collect());
+    let valid = |size: i32| size > 0 && size as usize <= MAX_MODEL_SIZE;
+    if !valid(size_x) || !valid(size_y) || !valid(size_z) {
+        return Err(VoxFileError::InvalidData(format!("invalid size {}x{}x{}", size_x, size_y, size_z)));
+    }
+    if palette.len() < 256*4 {
+        return Err(VoxFileError::InvalidData("palette too short".to_string()));
+    }
+
+    let count = content_i32(&voxels, 0)?;
+    if count < 0 || voxels.len() < 4 + count as usize * 4 {
+        return Err(VoxFileError::InvalidData("voxel count exceeds XYZI chunk".to_string()));
+    }
+
+    // MagicaVoxel is z-up, the world is y-up
+    let mut world = World::new(size_x as usize, size_z as usize, size_y as usize);
+    for i in voxels[4..4 + count as usize * 4].chunks_exact(4) {
+        let (x, z, y, color_index) = (i[0] as usize, i[1] as usize, i[2] as usize, i[3] as usize);
+        if !world.is_in_bounds(x, y, z) || color_index == 0 {
+            return Err(VoxFileError::InvalidData(format!("invalid voxel at {} {} {}", x, z, y)));
+        }
+        let color = &palette[(color_index - 1)*4..color_index*4];
+        world.place(mapping.get_material((color[0], color[1], color[2])), x, y, z);
+    }
+
+    Ok(world)
+}
+
+fn write_chunk(writer: &mut impl Write, id: &[u8; 4], content: &[u8], children_size: usize) -> Result<(), VoxFileError> {
+    writer.write_all(id)?;
+    writer.write_all(&(content.len() as i32).to_le_bytes())?;
+    writer.write_all(&(children_size as i32).to_le_bytes())?;
+    writer.write_all(content)?;
+    Ok(())
+}
+
+pub fn export(world: &World, writer: &mut impl Write) -> Result<(), VoxFileError> {
+    let (width, height, depth) = (world.get_width(), world.get_height(), world.get_depth());
+    if width > MAX_MODEL_SIZE || height > MAX_MODEL_SIZE || depth > MAX_MODEL_SIZE {
+        return Err(VoxFileError::WorldTooLarge(width, height, depth));
+    }
+
+    let mut size = vec![];
+    for i in [width, depth, height] {
+        size.extend((i as i32).to_le_bytes());
+    }
+
+    let occupied = world.get_occupied();
+    let mut voxels = (occupied.len() as i32).to_le_bytes().to_vec();
+    for (x, y, z) in occupied {
+        voxels.extend([x as u8, z as u8, y as u8, world.get(x, y, z).get_id() as u8]);
+    }
+
+    let mut palette = UNUSED_PALETTE_COLOR.repeat(256);
+    for material in VoxelMaterial::get_all() {
+        if material == VoxelMaterial::AIR {
+            continue;
+        }
+        let (r, g, b) = material.get_color();
+        let index = (material.get_id() as usize - 1)*4;
+        palette[index..index+4].copy_from_slice(&[r, g, b, 0xff]);
+    }
+
+    let children_size = 3*12 + size.len() + voxels.len() + palette.len();
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    write_chunk(writer, b"MAIN", &[], children_size)?;
+    write_chunk(writer, b"SIZE", &size, 0)?;
+    write_chunk(writer, b"XYZI", &voxels, 0)?;
+    write_chunk(writer, b"RGBA", &palette, 0)?;
+
+    Ok(())
+}
+
+pub fn import_from_path(path: &Path, mapping: &VoxMaterialMapping) -> Result<World, VoxFileError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    import(&mut reader, mapping)
+}
+
+pub fn export_to_path(world: &World, path: &Path) -> Result<(), VoxFileError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    export(world, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{export, import, VoxFileError, VoxMaterialMapping, MAGIC, VERSION};
+    use crate::model::{VoxelMaterial, World};
+
+    #[test]
+    fn test_round_trip() {
+        let materials = VoxelMaterial::get_all();
+        let width = materials.len();
+        let mut world = World::new(width, 4, 20);
+        for (i, material) in materials.into_iter().enumerate() {
+            world.set(material, i, 1, 19 - i);
+        }
+        world.set(VoxelMaterial::from_name("Stone").unwrap(), 12, 3, 0);
+
+        let mut buffer = vec![];
+        export(&world, &mut buffer).unwrap();
+        let loaded = import(&mut buffer.as_slice(), &VoxMaterialMapping::new()).unwrap();
+
+        assert_eq!((loaded.get_width(), loaded.get_height(), loaded.get_depth()), (width, 4, 20));
+        for y in 0..4 {
+            for z in 0..20 {
+                for x in 0..width {
+                    assert_eq!(world.get(x, y, z).get_id(), loaded.get(x, y, z).get_id());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_mapping() {
+        let mapping = VoxMaterialMapping::parse("# artist palette\nff0000 Lava\n0000ff Salt Water\n").unwrap();
+        assert_eq!(mapping.get_material((0xf0, 0x10, 0x10)), VoxelMaterial::from_name("Lava").unwrap());
+        assert_eq!(mapping.get_material((0x10, 0x20, 0xe0)), VoxelMaterial::from_name("Salt Water").unwrap());
+
+        let mut world = World::new(2, 2, 2);
+        world.set(VoxelMaterial::from_name("Oil").unwrap(), 0, 0, 0);
+        let mut buffer = vec![];
+        export(&world, &mut buffer).unwrap();
+        let loaded = import(&mut buffer.as_slice(), &mapping).unwrap();
+        assert_eq!(loaded.get(0, 0, 0), VoxelMaterial::from_name("Lava").unwrap());
+
+        assert!(VoxMaterialMapping::parse("ff0000 Clay").is_err());
+        assert!(VoxMaterialMapping::parse("red Sand").is_err());
+        assert!(VoxMaterialMapping::parse("").is_err());
+    }
+
+    fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut buffer = id.to_vec();
+        buffer.extend((content.len() as i32).to_le_bytes());
+        buffer.extend((children.len() as i32).to_le_bytes());
+        buffer.extend(content);
+        buffer.extend(children);
+        buffer
+    }
+
+    fn file(main: &[u8]) -> Vec<u8> {
+        let mut buffer = MAGIC.to_vec();
+        buffer.extend(VERSION.to_le_bytes());
+        buffer.extend(main);
+        buffer
+    }
+
+    #[test]
+    fn test_default_palette() {
+        let mut size = vec![];
+        for i in [2i32, 1, 1] {
+            size.extend(i.to_le_bytes());
+        }
+        // index 36 is pure red and 211 pure blue in the default palette
+        let mut voxels = 2i32.to_le_bytes().to_vec();
+        voxels.extend([0, 0, 0, 36, 1, 0, 0, 211]);
+        let mut children = chunk(b"SIZE", &size, &[]);
+        children.extend(chunk(b"XYZI", &voxels, &[]));
+
+        let mapping = VoxMaterialMapping::parse("ff0000 Lava\n0000ff Water\n").unwrap();
+        let loaded = import(&mut file(&chunk(b"MAIN", &[], &children)).as_slice(), &mapping).unwrap();
+        assert_eq!(loaded.get(0, 0, 0), VoxelMaterial::from_name("Lava").unwrap());
+        assert_eq!(loaded.get(1, 0, 0), VoxelMaterial::from_name("Water").unwrap());
+    }
+
+    #[test]
+    fn test_oversized_chunks() {
+        let mut main = chunk(b"MAIN", &[], &[]);
+        main[8..12].copy_from_slice(&i32::MAX.to_le_bytes());
+        let result = import(&mut file(&main).as_slice(), &VoxMaterialMapping::new());
+        assert!(matches!(result, Err(VoxFileError::InvalidData(_))));
+
+        let mut size = chunk(b"SIZE", &[0; 12], &[]);
+        size[4..8].copy_from_slice(&i32::MAX.to_le_bytes());
+        let result = import(&mut file(&chunk(b"MAIN", &[], &size)).as_slice(), &VoxMaterialMapping::new());
+        assert!(matches!(result, Err(VoxFileError::InvalidData(_))));
+    }
+
+    #[test]
+    fn test_oversized_model() {
+        let mut size = vec![];
+        for i in [2000i32, 1, 1] {
+            size.extend(i.to_le_bytes());
+        }
+        let mut children = chunk(b"SIZE", &size, &[]);
+        children.extend(chunk(b"XYZI", &0i32.to_le_bytes(), &[]));
+        let result = import(&mut file(&chunk(b"MAIN", &[], &children)).as_slice(), &VoxMaterialMapping::new());
+        assert!(matches!(result, Err(VoxFileError::InvalidData(_))));
+    }
+
+    #[test]
+    fn test_too_large() {
+        let world = World::new(257, 1, 1);
+        assert!(matches!(export(&world, &mut vec![]), Err(VoxFileError::WorldTooLarge(257, 1, 1))));
+    }
+}
@@ -1,19 +1,90 @@
-use rand::{rngs::ThreadRng, thread_rng, Rng};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
 use rand::seq::SliceRandom;
+use rayon::prelude::*;
+
+use crate::heat_simulator::HeatSimulator;
+use crate::structure_simulator::StructureSimulator;
+use crate::material_reactions::{MaterialReactions, Reaction};
+use crate::{material_properties::{MaterialProperties, MaterialType}, model::{VoxelMaterial, World, WorldRegion}};
+
+const TICK_SEED_MULTIPLIER: u64 = 0x9e37_79b9_7f4a_7c15;
+const CHUNK_SEED_MULTIPLIER: u64 = 0xbf58_476d_1ce4_e5b9;
+// chunks are scheduled in a 3x3x3 pattern, so regions running at the same time never share a chunk
+const PHASES: usize = 27;
+// how many voxels of a connected liquid body are searched for a lower free cell
+const PRESSURE_SEARCH_LIMIT: usize = 256;
+// voxels per tick, a fall has to stay within the chunks next to the falling voxel's own
+pub(crate) const GRAVITY: f32 = 0.5;
+pub(crate) const TERMINAL_VELOCITY: f32 = 8.0;
+const SPLASH_VELOCITY: f32 = 3.0;
+/// Chance per tick that a burning voxel gives off a flame into a free cell next to it.
+const FLAME_CHANCE: f64 = 0.3;
+
+type Position = (usize, usize, usize);
+
+pub trait ChangeListener {
+    fn voxel_changed(&mut self, x: usize, y: usize, z: usize);
+}
+impl ChangeListener for () {
+    fn voxel_changed(&mut self, _x: usize, _y: usize, _z: usize) {}
+}
 
-use crate::material_reactions::MaterialReactions;
-use crate::scene_map::SceneMap;
-use crate::{material_properties::{MaterialProperties, MaterialType}, model::{VoxelMaterial, World, WORLD_SIZE}};
+struct ChunkTask<'a>{
+    region: WorldRegion<'a>,
+    rng: StdRng,
+    changes: Vec<Position>,
+    // liquid voxels already known to have no free cell below the given level this tick
+    settled_levels: HashMap<Position, usize>
+}
+
+#[derive(Clone, Copy)]
+enum Pass {
+    Movement, Reactions
+}
+
+/// Voxels processed earlier in a tick get the first chance to take free cells, so the horizontal sweep
+/// direction is picked at random every tick.
+#[derive(Clone, Copy)]
+struct Sweep{
+    reverse_x: bool,
+    reverse_z: bool
+}
+impl Sweep {
+    fn order(min: usize, max: usize, reverse: bool) -> Vec<usize> {
+        if reverse {
+            (min..max).rev().collect()
+        }else{
+            (min..max).collect()
+        }
+    }
+}
 
 pub struct VoxelSimulator{
-    rng: ThreadRng,
+    seed: u64,
     down_neighbours: Vec<(isize, isize, isize)>,
     side_neighbours: Vec<(isize, isize, isize)>,
     up_neighbours: Vec<(isize, isize, isize)>,
-    all_neighbours: Vec<(isize, isize, isize)>
+    all_neighbours: Vec<(isize, isize, isize)>,
+    face_neighbours: Vec<(isize, isize, isize)>,
+    heat_simulator: HeatSimulator,
+    structure_simulator: StructureSimulator,
+    reactions: &'static MaterialReactions,
+    parallel: bool,
+    tick: u64
+}
+impl Default for VoxelSimulator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl VoxelSimulator {
     pub fn new() -> VoxelSimulator {
+        Self::with_seed(thread_rng().gen())
+    }
+
+    pub fn with_seed(seed: u64) -> VoxelSimulator {
         let down_neighbours = vec![(-1,-1,1), (0,-1,1), (1,-1,1), (1,-1,0), (1,-1,-1), (0,-1,-1), (-1,-1,-1), (-1,-1,0)];
         let side_neighbours = vec![(-1,0,1), (0,0,1), (1,0,1), (1,0,0), (1,0,-1), (0,0,-1), (-1,0,-1), (-1,0,0)];
         let up_neighbours = vec![(-1,1,1), (0,1,1), (1,1,1), (1,1,0), (1,1,-1), (0,1,-1), (-1,1,-1), (-1,1,0), (0,1,0)];
@@ -21,260 +92,1316 @@ impl VoxelSimulator {
         all_neighbours.append(&mut down_neighbours.clone());
         all_neighbours.append(&mut side_neighbours.clone());
         all_neighbours.append(&mut up_neighbours.clone());
+        let face_neighbours = vec![(1,0,0), (-1,0,0), (0,1,0), (0,-1,0), (0,0,1), (0,0,-1)];
+
 
+        Self {
+            seed,
+            down_neighbours,
+            side_neighbours,
+            up_neighbours,
+            all_neighbours,
+            face_neighbours,
+            heat_simulator: HeatSimulator::new(),
+            structure_simulator: StructureSimulator::new(),
+            reactions: MaterialReactions::get(),
+            parallel: true,
+            tick: 0
+        }
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
 
-        Self { rng: thread_rng(), down_neighbours, side_neighbours, up_neighbours, all_neighbours }
+    fn chunk_rng(&self, (chunk_x, chunk_y, chunk_z): Position, pass: Pass) -> StdRng {
+        let chunk = chunk_x as u64 | (chunk_y as u64) << 21 | (chunk_z as u64) << 42;
+        let key = (chunk << 1 | pass as u64).wrapping_add(1).wrapping_mul(CHUNK_SEED_MULTIPLIER);
+        StdRng::seed_from_u64(self.seed ^ self.tick.wrapping_mul(TICK_SEED_MULTIPLIER) ^ key)
     }
 
-    fn check_activity(&mut self, properties: &MaterialProperties) -> bool {
-        properties.activity >= 1.0 || properties.activity > self.rng.gen_range(0.0..1.0)
+    pub fn get_tick(&self) -> u64 {
+        self.tick
     }
 
+    pub fn set_tick(&mut self, tick: u64) {
+        self.tick = tick;
+    }
+
+    pub fn set_reactions(&mut self, reactions: &'static MaterialReactions) {
+        self.reactions = reactions;
+    }
+
+    pub fn is_parallel(&self) -> bool {
+        self.parallel
+    }
+
+    /// Chooses whether chunks are simulated on the rayon thread pool. Both modes give identical results.
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.parallel = parallel;
+    }
+
+    fn check_activity(task: &mut ChunkTask, properties: &MaterialProperties) -> bool {
+        properties.activity >= 1.0 || properties.activity > task.rng.gen_range(0.0..1.0)
+    }
+
+    /// Viscous liquids only flow sideways on some ticks.
+    fn check_viscosity(task: &mut ChunkTask, properties: &MaterialProperties) -> bool {
+        properties.viscosity <= 0.0 || properties.viscosity <= task.rng.gen_range(0.0..1.0)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn swap(
-        &mut self,
-        world: &mut World,
-        scene_map: &mut SceneMap,
-        material: VoxelMaterial,
+        task: &mut ChunkTask,
         properties: &MaterialProperties,
         x1: usize, y1: usize, z1: usize,
-        x2: usize, y2: usize, z2: usize,
-        mask: &mut [bool]) -> bool {
-        if x2 >= WORLD_SIZE || y2 >= WORLD_SIZE || z2 >= WORLD_SIZE {
+        x2: usize, y2: usize, z2: usize) -> bool {
+        if !Self::can_swap(&task.region, properties, x2, y2, z2) {
+            return false;
+        }
+        let region = &mut task.region;
+        if region.is_moved(x1, y1, z1) || region.is_moved(x2, y2, z2) {
+            // the move can still happen next tick
+            region.wake(x1, y1, z1);
             return false;
         }
-        if mask[World::coordiantes_to_index(x1, y1, z1)] || mask[World::coordiantes_to_index(x2, y2, z2)] {
-            false;
-        } 
 
-        let other = world.get(x2, y2, z2);
-        if matches!(other, VoxelMaterial::Air){
-            world.set(material, x2, y2, z2);
-            world.set(other, x1, y1, z1);
-            mask[World::coordiantes_to_index(x2, y2, z2)] = true;
+        let other = region.get(x2, y2, z2);
+        if other == VoxelMaterial::AIR {
+            region.swap(x1, y1, z1, x2, y2, z2);
+            region.set_moved(x2, y2, z2);
 
-            scene_map.update(x1, y1, z1);
-            scene_map.update(x2, y2, z2);
+            task.changes.push((x1, y1, z1));
+            task.changes.push((x2, y2, z2));
             true
         }else{
-            let other_properties = MaterialProperties::new(&other);
-            if matches!(other_properties.material_type, MaterialType::SOLID) {
-                false
-            }else if properties.weight > other_properties.weight {
-                world.set(material, x2, y2, z2);
-                world.set(other, x1, y1, z1);
+            region.swap(x1, y1, z1, x2, y2, z2);
+            region.set_velocity(0.0, x1, y1, z1);
 
-                mask[World::coordiantes_to_index(x1, y1, z1)] = true;
-                mask[World::coordiantes_to_index(x2, y2, z2)] = true;
+            region.set_moved(x1, y1, z1);
+            region.set_moved(x2, y2, z2);
 
 
-                scene_map.update(x1, y1, z1);
-                scene_map.update(x2, y2, z2);
-                true
-            }else{
-                false
+            task.changes.push((x1, y1, z1));
+            task.changes.push((x2, y2, z2));
+            true
+        }
+    }
+
+    fn can_swap(region: &WorldRegion, properties: &MaterialProperties, x: usize, y: usize, z: usize) -> bool {
+        if !region.is_in_bounds(x, y, z) {
+            return false;
+        }
+        let other = region.get(x, y, z);
+        if other == VoxelMaterial::AIR {
+            return true;
+        }
+        let other_properties = MaterialProperties::new(&other);
+        !matches!(other_properties.material_type, MaterialType::SOLID) && properties.weight > other_properties.weight
+    }
+
+    /// Keeps the chunk awake when a voxel stayed only because of a failed roll.
+    fn wake_if_movable(task: &mut ChunkTask, properties: &MaterialProperties, neighbours: &[(isize, isize, isize)], x: usize, y: usize, z: usize) {
+        let movable = neighbours.iter()
+            .map(|i| (x as isize + i.0, y as isize + i.1, z as isize + i.2))
+            .filter(|(other_x, other_y, other_z)| *other_x >= 0 && *other_y >= 0 && *other_z >= 0)
+            .any(|(other_x, other_y, other_z)| Self::can_swap(&task.region, properties, other_x as usize, other_y as usize, other_z as usize));
+        if movable {
+            task.region.wake(x, y, z);
+        }
+    }
+
+    /// Advances the age of a voxel with a limited lifetime, returns whether it expired.
+    fn age_voxel(task: &mut ChunkTask, x: usize, y: usize, z: usize) -> bool {
+        let region = &mut task.region;
+        let Some(lifetime) = MaterialProperties::new(&region.get(x, y, z)).lifetime else {
+            return false;
+        };
+        let age = region.get_age(x, y, z).saturating_add(1);
+        region.set_age(age, x, y, z);
+        region.wake(x, y, z);
+        if !task.rng.gen_bool(lifetime.get_expire_chance(age)) {
+            return false;
+        }
+        region.place(lifetime.on_expire, x, y, z);
+        task.changes.push((x, y, z));
+        true
+    }
+
+    /// Ignites a flammable voxel next to anything at its ignition temperature and advances a burning one, returns
+    /// whether it is burning. A burning voxel keeps its burn time in its age.
+    fn burn(&self, task: &mut ChunkTask, x: usize, y: usize, z: usize) -> bool {
+        let region = &mut task.region;
+        let Some(combustion) = MaterialProperties::new(&region.get(x, y, z)).combustion else {
+            return false;
+        };
+        let neighbours: Vec<Position> = self.face_neighbours.iter()
+            .map(|i| (x as isize + i.0, y as isize + i.1, z as isize + i.2))
+            .filter(|(other_x, other_y, other_z)| *other_x >= 0 && *other_y >= 0 && *other_z >= 0)
+            .map(|(other_x, other_y, other_z)| (other_x as usize, other_y as usize, other_z as usize))
+            .filter(|(other_x, other_y, other_z)| region.is_in_bounds(*other_x, *other_y, *other_z))
+            .collect();
+        let age = region.get_age(x, y, z);
+        if age == 0 {
+            let hot = std::iter::once((x, y, z)).chain(neighbours.iter().copied())
+                .any(|(other_x, other_y, other_z)| region.get_temperature(other_x, other_y, other_z) >= combustion.ignition_temperature);
+            if !hot {
+                return false;
             }
+            region.wake(x, y, z);
+            if !task.rng.gen_bool(combustion.flammability as f64) {
+                return false;
+            }
+        }
+
+        if age >= combustion.burn_duration {
+            region.place(combustion.residue, x, y, z);
+            task.changes.push((x, y, z));
+            return true;
         }
+        region.set_age(age + 1, x, y, z);
+        region.set_temperature(combustion.burn_temperature, x, y, z);
+        region.wake(x, y, z);
+        let free: Vec<Position> = neighbours.into_iter()
+            .filter(|(other_x, other_y, other_z)| region.get(*other_x, *other_y, *other_z) == VoxelMaterial::AIR)
+            .collect();
+        if let Some(&(flame_x, flame_y, flame_z)) = free.choose(&mut task.rng) {
+            if task.rng.gen_bool(FLAME_CHANCE) {
+                region.place(combustion.flame, flame_x, flame_y, flame_z);
+                task.changes.push((flame_x, flame_y, flame_z));
+            }
+        }
+        true
     }
 
-    fn check_reaction(
-        &mut self,
-        world: &mut World,
-        scene_map: &mut SceneMap,
-        x: usize,
-        y: usize,
-        z: usize){
-        let material = world.get(x, y, z);
-        let mut reactions = MaterialReactions::new(&material);
-        if reactions.is_empty() {
+    fn check_reaction(&self, task: &mut ChunkTask, x: usize, y: usize, z: usize){
+        let region = &mut task.region;
+        let material = region.get(x, y, z);
+        let reactions = self.reactions;
+        if !reactions.has_reactions(material) {
             return;
         }
-        
-        reactions.shuffle(&mut self.rng);
+
+        let temperature = region.get_temperature(x, y, z);
         let mut check_neighbours = self.all_neighbours.clone();
-        check_neighbours.shuffle(&mut self.rng);
-        for i in reactions {
-            if !self.rng.gen_bool(i.probability as f64) {
+        check_neighbours.shuffle(&mut task.rng);
+        let neighbours: Vec<(Position, VoxelMaterial)> = check_neighbours.iter()
+            .map(|j| (j.0 + x as isize, j.1 + y as isize, j.2 + z as isize))
+            .filter(|(other_x, other_y, other_z)| *other_x >= 0 && *other_y >= 0 && *other_z >= 0)
+            .map(|(other_x, other_y, other_z)| (other_x as usize, other_y as usize, other_z as usize))
+            .filter(|(other_x, other_y, other_z)| region.is_in_bounds(*other_x, *other_y, *other_z))
+            .map(|(other_x, other_y, other_z)| ((other_x, other_y, other_z), region.get(other_x, other_y, other_z)))
+            .collect();
+        let mut checked = Vec::with_capacity(neighbours.len());
+        for (other_position, other) in &neighbours {
+            // every reaction is rolled once per tick, however many neighbours could take part in it
+            if checked.contains(other) {
+                continue;
+            }
+            checked.push(*other);
+            let Some(reaction) = reactions.get_reaction(material, *other) else {
+                continue;
+            };
+            if !reaction.conditions.is_met(temperature) {
+                continue;
+            }
+            let Some((third_position, spawn_position)) = Self::find_participants(reaction, *other_position, &neighbours) else {
+                continue;
+            };
+            if !task.rng.gen_bool(reaction.probability as f64) {
+                region.wake(x, y, z);
                 continue;
             }
 
-            for j in &check_neighbours {
-                let other_x = j.0 + x as isize;
-                let other_y = j.1 + y as isize;
-                let other_z = j.2 + z as isize;
-                if other_x < 0 || other_x >= WORLD_SIZE as isize ||
-                    other_y < 0 || other_y >= WORLD_SIZE as isize ||
-                    other_z < 0 || other_z >= WORLD_SIZE as isize {
-                    continue;
-                }
-
-                if world.get(other_x as usize, other_y as usize, other_z as usize).get_id() == i.other_material.get_id() {
-                    world.set(i.first_product, x, y, z);
-                    world.set(i.second_product, other_x as usize, other_y as usize, other_z as usize);
-                    scene_map.update(x, y, z);
-                    scene_map.update(other_x as usize, other_y as usize, other_z as usize);
-                    return;
-                }
+            let (other_x, other_y, other_z) = *other_position;
+            region.set(reaction.first_product, x, y, z);
+            region.set(reaction.second_product, other_x, other_y, other_z);
+            task.changes.push((x, y, z));
+            task.changes.push((other_x, other_y, other_z));
+            if let Some((third_x, third_y, third_z)) = third_position {
+                region.set(reaction.third_product, third_x, third_y, third_z);
+                task.changes.push((third_x, third_y, third_z));
+            }
+            if let (Some(spawn), Some((spawn_x, spawn_y, spawn_z))) = (reaction.spawn, spawn_position) {
+                region.place(spawn, spawn_x, spawn_y, spawn_z);
+                task.changes.push((spawn_x, spawn_y, spawn_z));
             }
+            return;
         }
     }
 
-    fn simulate_liquid(
-        &mut self,
-        world: &mut World,
-        scene_map: &mut SceneMap,
-        material: VoxelMaterial,
-        properties: MaterialProperties,
+    fn find_participants(
+        reaction: &Reaction,
+        other_position: Position,
+        neighbours: &[(Position, VoxelMaterial)]) -> Option<(Option<Position>, Option<Position>)> {
+        let other = neighbours.iter().find(|(position, _)| *position == other_position)?.1;
+        let count = neighbours.iter().filter(|(_, material)| *material == other).count();
+        if count < reaction.conditions.min_count as usize {
+            return None;
+        }
+        if let Some(catalyst) = reaction.conditions.catalyst {
+            neighbours.iter().find(|(_, material)| *material == catalyst)?;
+        }
+
+        let third_position = match reaction.third {
+            Some(third) => Some(neighbours.iter()
+                .find(|(position, material)| *material == third && *position != other_position)?.0),
+            None => None,
+        };
+        let spawn_position = match reaction.spawn {
+            Some(_) => Some(neighbours.iter()
+                .find(|(position, material)| *material == VoxelMaterial::AIR &&
+                    *position != other_position &&
+                    Some(*position) != third_position)?.0),
+            None => None,
+        };
+
+        Some((third_position, spawn_position))
+    }
+
+    fn try_neighbours(
+        task: &mut ChunkTask,
+        properties: &MaterialProperties,
+        neighbours: &[(isize, isize, isize)],
         x: usize,
         y: usize,
-        z: usize,
-        mask: &mut [bool]) {
-        if y > 0 {
-            if self.swap(world, scene_map, material, &properties, x, y, z, x, y-1, z, mask) {
+        z: usize) -> bool {
+        for i in neighbours {
+            let other_x = x as isize + i.0;
+            let other_y = y as isize + i.1;
+            let other_z = z as isize + i.2;
+            if other_x < 0 || other_y < 0 || other_z < 0 {
+                continue;
+            }
+
+            if Self::swap(task, properties, x, y, z, other_x as usize, other_y as usize, other_z as usize) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Falls as far as the voxel's velocity allows, stopping at the first obstacle on the way. Returns whether
+    /// the voxel is done for this tick.
+    fn fall(task: &mut ChunkTask, properties: &MaterialProperties, x: usize, y: usize, z: usize) -> bool {
+        if y == 0 {
+            return false;
+        }
+        let velocity = (task.region.get_velocity(x, y, z) - GRAVITY).max(-TERMINAL_VELOCITY);
+        let distance = (-velocity as usize).max(1);
+        let mut target_y = y;
+        while target_y > 0 && y - target_y < distance && task.region.get(x, target_y - 1, z) == VoxelMaterial::AIR {
+            target_y -= 1;
+        }
+        if target_y < y {
+            if Self::swap(task, properties, x, y, z, x, target_y, z) {
+                task.region.set_velocity(velocity, x, target_y, z);
+                return true;
+            }
+            return false;
+        }
+
+        // sinking through a lighter material is slow, and liquids drag it further
+        if !Self::can_swap(&task.region, properties, x, y-1, z) {
+            return false;
+        }
+        let other = MaterialProperties::new(&task.region.get(x, y-1, z));
+        if matches!(other.material_type, MaterialType::LIQUID) && -velocity >= SPLASH_VELOCITY {
+            if Self::swap(task, properties, x, y, z, x, y-1, z) {
+                task.region.set_velocity(0.0, x, y-1, z);
+                Self::throw_up(task, -velocity, x, y, z);
+                return true;
+            }
+            return false;
+        }
+        if matches!(other.material_type, MaterialType::LIQUID) && !task.rng.gen_bool(properties.get_sink_chance(&other)) {
+            task.region.wake(x, y, z);
+            return true;
+        }
+        if Self::swap(task, properties, x, y, z, x, y-1, z) {
+            task.region.set_velocity(0.0, x, y-1, z);
+            return true;
+        }
+        false
+    }
+
+    /// Throws a liquid displaced by a fast impact up through the air above it, half as high as the impact speed.
+    fn throw_up(task: &mut ChunkTask, impact: f32, x: usize, y: usize, z: usize) {
+        let region = &mut task.region;
+        let mut target_y = y;
+        while target_y - y < (impact / 2.0) as usize && region.is_in_bounds(x, target_y + 1, z) &&
+            region.get(x, target_y + 1, z) == VoxelMaterial::AIR {
+            target_y += 1;
+        }
+        if target_y > y {
+            region.swap(x, y, z, x, target_y, z);
+            region.set_moved(x, target_y, z);
+            task.changes.push((x, y, z));
+            task.changes.push((x, target_y, z));
+        }
+    }
+
+    fn simulate_liquid(&self, task: &mut ChunkTask, properties: MaterialProperties, x: usize, y: usize, z: usize) {
+        if Self::fall(task, &properties, x, y, z) {
+            return;
+        }
+        let impact = -task.region.get_velocity(x, y, z);
+        task.region.set_velocity(0.0, x, y, z);
+        if impact >= SPLASH_VELOCITY {
+            let mut side_neighbours = self.side_neighbours.clone();
+            side_neighbours.shuffle(&mut task.rng);
+            if Self::spread(task, &properties, &side_neighbours, impact as u32, x, y, z) {
                 return;
             }
-            
+        }
+
+        if y > 0 {
             let mut down_neighbours = self.down_neighbours.clone();
-            down_neighbours.shuffle(&mut self.rng);
+            down_neighbours.shuffle(&mut task.rng);
+            if Self::try_neighbours(task, &properties, &down_neighbours, x, y, z) {
+                return;
+            }
+        }
 
-            for i in down_neighbours {
-                let other_x = x as isize + i.0;
-                let other_y = y as isize + i.1;
-                let other_z = z as isize + i.2;
-                if other_x < 0 || other_y < 0 || other_z < 0 {
-                    continue;
+        if !Self::check_viscosity(task, &properties){
+            Self::wake_if_movable(task, &properties, &self.side_neighbours, x, y, z);
+            return;
+        }
+
+        // a voxel on top of its own body levels out first, spreading would just move it back and forth across
+        // the surface while another part of the body sits lower
+        let on_body = y > 0 && task.region.get(x, y-1, z) == task.region.get(x, y, z);
+        if on_body && self.equalize_pressure(task, &properties, x, y, z) {
+            return;
+        }
+        let mut side_neighbours = self.side_neighbours.clone();
+        side_neighbours.shuffle(&mut task.rng);
+        if Self::spread(task, &properties, &side_neighbours, properties.get_flow_distance(), x, y, z) {
+            return;
+        }
+        if !on_body {
+            self.equalize_pressure(task, &properties, x, y, z);
+        }
+    }
+
+    /// Liquids only separate vertically, pushing a lighter liquid aside would just stir the layers.
+    fn can_spread(region: &WorldRegion, properties: &MaterialProperties, x: usize, y: usize, z: usize) -> bool {
+        Self::can_swap(region, properties, x, y, z) &&
+            !matches!(MaterialProperties::new(&region.get(x, y, z)).material_type, MaterialType::LIQUID)
+    }
+
+    /// Moves a liquid up to the given distance along the first open direction, stopping above the first hole.
+    fn spread(
+        task: &mut ChunkTask,
+        properties: &MaterialProperties,
+        directions: &[(isize, isize, isize)],
+        max_distance: u32,
+        x: usize,
+        y: usize,
+        z: usize) -> bool {
+        for (dx, _, dz) in directions {
+            let mut target = None;
+            for distance in 1..=max_distance as isize {
+                let other_x = x as isize + dx*distance;
+                let other_z = z as isize + dz*distance;
+                if other_x < 0 || other_z < 0 || !Self::can_spread(&task.region, properties, other_x as usize, y, other_z as usize) {
+                    break;
+                }
+                target = Some((other_x as usize, other_z as usize));
+                if y > 0 && Self::can_swap(&task.region, properties, other_x as usize, y-1, other_z as usize) {
+                    break;
                 }
+            }
 
-                if self.swap(world, scene_map, material, &properties, x, y, z, other_x as usize, other_y as usize, other_z as usize, mask) {
-                    return;
+            if let Some((other_x, other_z)) = target {
+                if Self::swap(task, properties, x, y, z, other_x, y, other_z) {
+                    return true;
                 }
             }
         }
 
-        if !self.check_activity(&properties){
-            return;
+        false
+    }
+
+    /// Moves the top voxel of a liquid column to the lowest cell it could move into (air or something lighter) next
+    /// to the connected body of the same liquid that is below it, so connected volumes settle at the same level.
+    /// Returns whether the voxel moved.
+    fn equalize_pressure(&self, task: &mut ChunkTask, properties: &MaterialProperties, x: usize, y: usize, z: usize) -> bool {
+        let region = &task.region;
+        let material = region.get(x, y, z);
+        if region.is_in_bounds(x, y+1, z) && region.get(x, y+1, z) == material {
+            return false;
+        }
+        if task.settled_levels.get(&(x, y, z)).is_some_and(|level| y <= *level) {
+            return false;
         }
 
-        let mut side_neighbours = self.side_neighbours.clone();
-        side_neighbours.shuffle(&mut self.rng);
+        let mut directions = self.face_neighbours.clone();
+        directions.shuffle(&mut task.rng);
+        let mut visited = HashSet::from([(x, y, z)]);
+        let mut queue = VecDeque::from([(x, y, z)]);
+        let mut searched = 0;
+        let mut outlet: Option<Position> = None;
+        while let Some((current_x, current_y, current_z)) = queue.pop_front() {
+            for (dx, dy, dz) in &directions {
+                let other_x = current_x as isize + dx;
+                let other_y = current_y as isize + dy;
+                let other_z = current_z as isize + dz;
+                if other_x < 0 || other_y < 0 || other_z < 0 {
+                    continue;
+                }
+                let position = (other_x as usize, other_y as usize, other_z as usize);
+                if !region.is_inside(position.0, position.1, position.2) || !visited.insert(position) {
+                    continue;
+                }
 
-        for i in side_neighbours {
-            let other_x = x as isize + i.0;
-            let other_y = y as isize + i.1;
-            let other_z = z as isize + i.2;
-            if other_x < 0 || other_y < 0 || other_z < 0 {
-                continue;
+                let other = region.get(position.0, position.1, position.2);
+                if other == material && searched < PRESSURE_SEARCH_LIMIT {
+                    searched += 1;
+                    queue.push_back(position);
+                }else if other != material && position.1 < y && outlet.is_none_or(|i| position.1 < i.1) &&
+                    Self::can_swap(region, properties, position.0, position.1, position.2) {
+                    outlet = Some(position);
+                }
             }
+        }
 
-            if self.swap(world, scene_map, material, &properties, x, y, z, other_x as usize, other_y as usize, other_z as usize, mask) {
-                return;
+        if let Some((outlet_x, outlet_y, outlet_z)) = outlet {
+            return Self::swap(task, properties, x, y, z, outlet_x, outlet_y, outlet_z);
+        }
+        if searched < PRESSURE_SEARCH_LIMIT {
+            // the whole body was searched, so nothing in it at this level or below can flow anywhere either
+            for position in visited {
+                if region.get(position.0, position.1, position.2) == material {
+                    let level = task.settled_levels.entry(position).or_insert(y);
+                    *level = (*level).max(y);
+                }
             }
         }
+        false
     }
 
-    fn simulate_powder(
-        &mut self,
-        world: &mut World,
-        scene_map: &mut SceneMap,
-        material: VoxelMaterial,
-        properties: MaterialProperties,
-        x: usize,
-        y: usize,
-        z: usize,
-        mask: &mut [bool]) {
-        if y <= 0 {
+    fn simulate_powder(&self, task: &mut ChunkTask, properties: MaterialProperties, x: usize, y: usize, z: usize) {
+        if Self::fall(task, &properties, x, y, z) {
             return;
         }
-
-        if self.swap(world, scene_map, material, &properties, x, y, z, x, y-1, z, mask) {
+        task.region.set_velocity(0.0, x, y, z);
+        if y == 0 {
             return;
         }
 
-        if !self.check_activity(&properties){
+        let (run, drop) = properties.get_repose_step(self.is_wet(&task.region, x, y, z));
+        if !Self::check_activity(task, &properties){
+            // only a grain that could slide keeps the chunk awake, a settled pile goes to sleep
+            let slidable = self.side_neighbours.iter()
+                .any(|direction| Self::get_slide_target(&task.region, &properties, *direction, run, drop, x, y, z).is_some());
+            if slidable {
+                task.region.wake(x, y, z);
+            }
             return;
         }
 
-        let mut down_neighbours = self.down_neighbours.clone();
-        down_neighbours.shuffle(&mut self.rng);
-    
-        for i in down_neighbours {
-            let other_x = x as isize + i.0;
-            let other_y = y as isize + i.1;
-            let other_z = z as isize + i.2;
-            if other_x < 0 || other_y < 0 || other_z < 0 {
-                continue;
-            }
-    
-            if self.swap(world, scene_map, material, &properties, x, y, z, other_x as usize, other_y as usize, other_z as usize, mask) {
-                return;
-            }
+        let mut side_neighbours = self.side_neighbours.clone();
+        side_neighbours.shuffle(&mut task.rng);
+        Self::slide(task, &properties, &side_neighbours, run, drop, x, y, z);
+    }
+
+    fn is_wet(&self, region: &WorldRegion, x: usize, y: usize, z: usize) -> bool {
+        self.face_neighbours.iter()
+            .map(|i| (x as isize + i.0, y as isize + i.1, z as isize + i.2))
+            .filter(|(other_x, other_y, other_z)| *other_x >= 0 && *other_y >= 0 && *other_z >= 0)
+            .map(|(other_x, other_y, other_z)| (other_x as usize, other_y as usize, other_z as usize))
+            .filter(|(other_x, other_y, other_z)| region.is_in_bounds(*other_x, *other_y, *other_z))
+            .any(|(other_x, other_y, other_z)| matches!(MaterialProperties::new(&region.get(other_x, other_y, other_z)).material_type, MaterialType::LIQUID))
+    }
+
+    /// Where a grain moves when the step of `run` voxels sideways in the direction and `drop` voxels down is free.
+    /// Long runs are walked one voxel per tick, so grains can come to rest on every column of a pile.
+    #[allow(clippy::too_many_arguments)]
+    fn get_slide_target(
+        region: &WorldRegion,
+        properties: &MaterialProperties,
+        (dx, _, dz): (isize, isize, isize),
+        run: usize,
+        drop: usize,
+        x: usize,
+        y: usize,
+        z: usize) -> Option<(usize, usize, usize)> {
+        if y < drop {
+            return None;
+        }
+        let target_x = x as isize + dx*run as isize;
+        let target_z = z as isize + dz*run as isize;
+        if target_x < 0 || target_z < 0 {
+            return None;
+        }
+        let (target_x, target_z) = (target_x as usize, target_z as usize);
+        let path_free = (1..run).all(|i| Self::can_swap(region, properties, (x as isize + dx*i as isize) as usize, y, (z as isize + dz*i as isize) as usize));
+        let drop_free = (1..=drop).all(|i| Self::can_swap(region, properties, target_x, y - i, target_z));
+
+        if !path_free || !drop_free {
+            return None;
+        }
+
+        if run > 1 {
+            Some(((x as isize + dx) as usize, y, (z as isize + dz) as usize))
+        }else{
+            Some((target_x, y - drop, target_z))
         }
     }
 
-    fn simulate_gas(
-        &mut self,
-        world: &mut World,
-        scene_map: &mut SceneMap,
-        material: VoxelMaterial,
-        properties: MaterialProperties,
+    /// Moves a grain along the first direction where the step matching its slope is free.
+    #[allow(clippy::too_many_arguments)]
+    fn slide(
+        task: &mut ChunkTask,
+        properties: &MaterialProperties,
+        directions: &[(isize, isize, isize)],
+        run: usize,
+        drop: usize,
         x: usize,
         y: usize,
-        z: usize,
-        mask: &mut [bool]) {
+        z: usize) -> bool {
+        for direction in directions {
+            let Some((target_x, target_y, target_z)) = Self::get_slide_target(&task.region, properties, *direction, run, drop, x, y, z) else {
+                continue;
+            };
+            if Self::swap(task, properties, x, y, z, target_x, target_y, target_z) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn simulate_gas(&self, task: &mut ChunkTask, properties: MaterialProperties, x: usize, y: usize, z: usize) {
         let mut neighbours;
-        
-        if self.rng.gen_bool(properties.activity as f64){
+
+        if task.rng.gen_bool(properties.activity as f64){
             neighbours = self.all_neighbours.clone();
         }else{
             neighbours = self.up_neighbours.clone();
             neighbours.append(&mut self.side_neighbours.clone());
         }
-        neighbours.shuffle(&mut self.rng);
-
-        for i in neighbours {
-            let other_x = x as isize + i.0;
-            let other_y = y as isize + i.1;
-            let other_z = z as isize + i.2;
-            if other_x < 0 || other_y < 0 || other_z < 0 {
-                continue;
-            }
-            if self.swap(world, scene_map, material, &properties, x, y, z, other_x as usize, other_y as usize, other_z as usize, mask) {
-                return;
-            }
+        neighbours.shuffle(&mut task.rng);
+        if !Self::try_neighbours(task, &properties, &neighbours, x, y, z) && neighbours.len() < self.all_neighbours.len() {
+            Self::wake_if_movable(task, &properties, &self.all_neighbours, x, y, z);
         }
     }
-    
-    fn simulate_voxel(&mut self, world: &mut World, scene_map: &mut SceneMap, x: usize, y: usize, z: usize, mask: &mut [bool]) {
-        let material = world.get(x, y, z);
+
+    fn simulate_voxel(&self, task: &mut ChunkTask, x: usize, y: usize, z: usize) {
+        let material = task.region.get(x, y, z);
         let material_properties = MaterialProperties::new(&material);
         match material_properties.material_type {
             MaterialType::SOLID => {},
-            MaterialType::LIQUID => {self.simulate_liquid(world, scene_map, material, material_properties, x, y, z, mask)},
-            MaterialType::POWDER => {self.simulate_powder(world, scene_map, material, material_properties, x, y, z, mask)},
-            MaterialType::GAS => {self.simulate_gas(world, scene_map, material, material_properties, x, y, z, mask)},
+            MaterialType::LIQUID => {self.simulate_liquid(task, material_properties, x, y, z)},
+            MaterialType::POWDER => {self.simulate_powder(task, material_properties, x, y, z)},
+            MaterialType::GAS => {self.simulate_gas(task, material_properties, x, y, z)},
         }
     }
 
-    pub fn next_step(&mut self, world: &mut World, scene_map: &mut SceneMap) {
-        let mut mask = [false; WORLD_SIZE*WORLD_SIZE*WORLD_SIZE];
-        for y in 0..WORLD_SIZE {
-            for z in 0..WORLD_SIZE {
-                for x in 0..WORLD_SIZE {
-                    if matches!(world.get(x, y, z), VoxelMaterial::Air) {
+    fn simulate_chunk(&self, task: &mut ChunkTask, pass: Pass, sweep: Sweep) {
+        let ((min_x, max_x), (min_y, max_y), (min_z, max_z)) = task.region.get_chunk_bounds();
+        let x_order = Sweep::order(min_x, max_x, sweep.reverse_x);
+        let z_order = Sweep::order(min_z, max_z, sweep.reverse_z);
+        for y in min_y..max_y {
+            for &z in &z_order {
+                for &x in &x_order {
+                    if task.region.get(x, y, z) == VoxelMaterial::AIR {
                         continue;
                     }
-                    self.simulate_voxel(world, scene_map, x, y, z, &mut mask);
+                    match pass {
+                        // voxels that already moved this tick would lose their velocity
+                        Pass::Movement if task.region.is_moved(x, y, z) => {},
+                        Pass::Movement => self.simulate_voxel(task, x, y, z),
+                        Pass::Reactions => if !Self::age_voxel(task, x, y, z) && !self.burn(task, x, y, z) {
+                            self.check_reaction(task, x, y, z)
+                        },
+                    }
                 }
             }
         }
-        for y in 0..WORLD_SIZE {
-            for z in 0..WORLD_SIZE {
-                for x in 0..WORLD_SIZE {
-                    if matches!(world.get(x, y, z), VoxelMaterial::Air) {
-                        continue;
-                    }           
-                    self.check_reaction(world, scene_map,  x, y, z);
+    }
+
+    fn choose_sweep(&self) -> Sweep {
+        let mut rng = StdRng::seed_from_u64(self.seed ^ self.tick.wrapping_mul(TICK_SEED_MULTIPLIER));
+        Sweep { reverse_x: rng.gen(), reverse_z: rng.gen() }
+    }
+
+    fn run_pass(&self, world: &mut World, listener: &mut dyn ChangeListener, pass: Pass, sweep: Sweep) {
+        let mut phases = Vec::with_capacity(PHASES);
+        for phase_y in 0..3 {
+            for phase_z in Sweep::order(0, 3, sweep.reverse_z) {
+                for phase_x in Sweep::order(0, 3, sweep.reverse_x) {
+                    phases.push(phase_x + phase_z*3 + phase_y*9);
+                }
+            }
+        }
+
+        for phase in phases {
+            // chunks filled by an earlier phase are picked up by their own phase later on, sleeping chunks are skipped
+            let chunks: Vec<Position> = world.get_scheduled_chunks()
+                .into_iter()
+                .filter(|(x, y, z)| x % 3 + z % 3 * 3 + y % 3 * 9 == phase)
+                .collect();
+            if chunks.is_empty() {
+                continue;
+            }
+
+            let mut tasks: Vec<ChunkTask> = world.split_regions(&chunks)
+                .into_iter()
+                .map(|region| {
+                    let rng = self.chunk_rng(region.get_chunk(), pass);
+                    ChunkTask { region, rng, changes: vec![], settled_levels: HashMap::new() }
+                })
+                .collect();
+            if self.parallel {
+                tasks.par_iter_mut().for_each(|task| self.simulate_chunk(task, pass, sweep));
+            }else{
+                tasks.iter_mut().for_each(|task| self.simulate_chunk(task, pass, sweep));
+            }
+
+            let structure_changed: Vec<Position> = tasks.iter()
+                .flat_map(|i| i.region.get_structure_changed().iter().copied())
+                .collect();
+            for task in tasks {
+                for (x, y, z) in task.changes {
+                    listener.voxel_changed(x, y, z);
+                }
+            }
+            for (chunk_x, chunk_y, chunk_z) in structure_changed {
+                world.mark_structure_changed(chunk_x, chunk_y, chunk_z);
+            }
+        }
+    }
+
+    pub fn next_step(&mut self, world: &mut World, listener: &mut dyn ChangeListener) {
+        self.heat_simulator.next_step(world, listener);
+        self.structure_simulator.next_step(world, listener);
+        world.begin_tick();
+        let sweep = self.choose_sweep();
+        self.run_pass(world, listener, Pass::Movement, sweep);
+        self.run_pass(world, listener, Pass::Reactions, sweep);
+        self.tick += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{ChangeListener, VoxelSimulator};
+    use crate::{chunk::CHUNK_SIZE, material_reactions::MaterialReactions, material_registry::MaterialRegistry, model::{VoxelMaterial, World}, world_file::{self, SaveMetadata}};
+
+    struct ChangeCounter{
+        count: usize
+    }
+    impl ChangeListener for ChangeCounter {
+        fn voxel_changed(&mut self, _x: usize, _y: usize, _z: usize) {
+            self.count += 1;
+        }
+    }
+
+    #[test]
+    fn test_sand_falls_without_window() {
+        let mut world = World::new(3, 10, 3);
+        world.set(VoxelMaterial::from_name("Sand").unwrap(), 1, 9, 1);
+        world.set(VoxelMaterial::from_name("Metal").unwrap(), 0, 0, 0);
+        let mut simulator = VoxelSimulator::with_seed(1);
+        let mut counter = ChangeCounter { count: 0 };
+
+        for _ in 0..20 {
+            simulator.next_step(&mut world, &mut counter);
+        }
+
+        assert_eq!(world.get(1, 0, 1), VoxelMaterial::from_name("Sand").unwrap());
+        assert_eq!(world.get(0, 0, 0), VoxelMaterial::from_name("Metal").unwrap());
+        // accelerating, the grain covers the 9 cells in 6 moves
+        assert_eq!(counter.count, 12);
+        assert_eq!(simulator.get_tick(), 20);
+    }
+
+    fn create_test_world() -> World {
+        let mut world = World::new(12, 16, 12);
+        for y in 4..16 {
+            for z in 3..9 {
+                for x in 3..9 {
+                    let material = match y {
+                        4..=7 => VoxelMaterial::from_name("Sand").unwrap(),
+                        8..=11 => VoxelMaterial::from_name("Water").unwrap(),
+                        12 => VoxelMaterial::from_name("Lava").unwrap(),
+                        13 => VoxelMaterial::from_name("Salt").unwrap(),
+                        _ => VoxelMaterial::from_name("Steam").unwrap(),
+                    };
+                    world.set(material, x, y, z);
+                }
+            }
+        }
+
+        world
+    }
+
+    fn assert_same(first: &World, second: &World) {
+        for y in 0..first.get_height() {
+            for z in 0..first.get_depth() {
+                for x in 0..first.get_width() {
+                    assert_eq!(first.get(x, y, z).get_id(), second.get(x, y, z).get_id(), "mismatch at {} {} {}", x, y, z);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_same_seed_is_deterministic() {
+        let mut first = create_test_world();
+        let mut second = create_test_world();
+        let mut first_simulator = VoxelSimulator::with_seed(1234);
+        let mut second_simulator = VoxelSimulator::with_seed(1234);
+
+        for _ in 0..60 {
+            first_simulator.next_step(&mut first, &mut ());
+            second_simulator.next_step(&mut second, &mut ());
+        }
+
+        assert_same(&first, &second);
+    }
+
+    #[test]
+    fn test_different_seeds_diverge() {
+        let mut first = create_test_world();
+        let mut second = create_test_world();
+        let mut first_simulator = VoxelSimulator::with_seed(1);
+        let mut second_simulator = VoxelSimulator::with_seed(2);
+
+        for _ in 0..30 {
+            first_simulator.next_step(&mut first, &mut ());
+            second_simulator.next_step(&mut second, &mut ());
+        }
+
+        assert!(first.get_occupied() != second.get_occupied());
+    }
+
+    #[test]
+    fn test_resume_from_save_is_deterministic() {
+        let mut world = create_test_world();
+        let mut simulator = VoxelSimulator::with_seed(99);
+        for _ in 0..20 {
+            simulator.next_step(&mut world, &mut ());
+        }
+
+        let mut buffer = vec![];
+        let metadata = SaveMetadata { seed: simulator.get_seed(), tick: simulator.get_tick() };
+        world_file::save(&world, metadata, &mut buffer).unwrap();
+        let (mut loaded, metadata) = world_file::load(&mut buffer.as_slice()).unwrap();
+        let mut resumed = VoxelSimulator::with_seed(metadata.seed);
+        resumed.set_tick(metadata.tick);
+
+        for _ in 0..20 {
+            simulator.next_step(&mut world, &mut ());
+            resumed.next_step(&mut loaded, &mut ());
+        }
+
+        assert_same(&world, &loaded);
+    }
+
+    fn create_large_world() -> World {
+        let mut world = World::new(32, 32, 32);
+        let materials = ["Sand", "Water", "Steam", "Salt", "Lava", "Oil"].map(|i| VoxelMaterial::from_name(i).unwrap());
+        for y in 0..32 {
+            for z in 0..32 {
+                for x in 0..32 {
+                    if (x*7 + y*13 + z*5) % 3 == 0 {
+                        world.place(materials[(x + y + z) % materials.len()], x, y, z);
+                    }
                 }
             }
         }
+
+        world
+    }
+
+    #[test]
+    fn test_parallel_matches_serial() {
+        let mut parallel = create_large_world();
+        let mut serial = create_large_world();
+        let mut parallel_simulator = VoxelSimulator::with_seed(7);
+        let mut serial_simulator = VoxelSimulator::with_seed(7);
+        serial_simulator.set_parallel(false);
+        let mut parallel_counter = ChangeCounter { count: 0 };
+        let mut serial_counter = ChangeCounter { count: 0 };
+
+        for _ in 0..4 {
+            parallel_simulator.next_step(&mut parallel, &mut parallel_counter);
+            serial_simulator.next_step(&mut serial, &mut serial_counter);
+        }
+
+        assert_same(&parallel, &serial);
+        assert_eq!(parallel_counter.count, serial_counter.count);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_voxels_move_once_across_chunks() {
+        let steam = VoxelMaterial::from_name("Steam").unwrap();
+        let sand = VoxelMaterial::from_name("Sand").unwrap();
+        for seed in 0..20 {
+            let mut world = World::new(1, 48, 1);
+            world.place(steam, 0, 15, 0);
+            world.place(steam, 0, 31, 0);
+            world.place(sand, 0, 32, 0);
+            let mut simulator = VoxelSimulator::with_seed(seed);
+
+            simulator.next_step(&mut world, &mut ());
+
+            let steam_heights: Vec<usize> = (0..48).filter(|y| world.get(0, *y, 0) == steam).collect();
+            assert_eq!(steam_heights.len(), 2);
+            assert!((14..=16).contains(&steam_heights[0]), "seed {}: {:?}", seed, steam_heights);
+            assert!((30..=32).contains(&steam_heights[1]), "seed {}: {:?}", seed, steam_heights);
+            assert_eq!((0..48).filter(|y| world.get(0, *y, 0) == sand).count(), 1);
+            assert!(world.get(0, 31, 0) == sand || world.get(0, 32, 0) == sand);
+        }
+    }
+
+    #[test]
+    fn test_settled_chunks_sleep() {
+        let sand = VoxelMaterial::from_name("Sand").unwrap();
+        let mut world = World::new(CHUNK_SIZE*2, CHUNK_SIZE, CHUNK_SIZE);
+        for y in 0..3 {
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE*2 {
+                    world.place(sand, x, y, z);
+                }
+            }
+        }
+        world.place(sand, CHUNK_SIZE + 3, 10, 3);
+        let mut simulator = VoxelSimulator::with_seed(1);
+
+        for _ in 0..10 {
+            simulator.next_step(&mut world, &mut ());
+        }
+        assert_eq!(world.get(CHUNK_SIZE + 3, 3, 3), sand);
+        assert!(world.get_active_chunks().is_empty());
+        let mut counter = ChangeCounter { count: 0 };
+        simulator.next_step(&mut world, &mut counter);
+        assert_eq!(counter.count, 0);
+
+        world.place(sand, 2, 10, 2);
+        assert_eq!(world.get_active_chunks(), vec![(0, 0, 0)]);
+        simulator.next_step(&mut world, &mut counter);
+        assert_eq!(world.get(2, 9, 2), sand);
+        assert_eq!(counter.count, 2);
+    }
+
+    #[test]
+    fn test_settled_world_does_no_heat_or_structure_work() {
+        let sand = VoxelMaterial::from_name("Sand").unwrap();
+        let metal = VoxelMaterial::from_name("Metal").unwrap();
+        let mut world = World::new(CHUNK_SIZE*4, CHUNK_SIZE*2, CHUNK_SIZE);
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE*4 {
+                world.place(sand, x, 0, z);
+            }
+        }
+        for y in 0..CHUNK_SIZE + 4 {
+            world.place(metal, 3, y, 3);
+        }
+        let mut simulator = VoxelSimulator::with_seed(1);
+
+        for _ in 0..5 {
+            simulator.next_step(&mut world, &mut ());
+        }
+        assert!(world.get_active_chunks().is_empty());
+        simulator.next_step(&mut world, &mut ());
+        assert_eq!(simulator.heat_simulator.get_visited_voxel_count(), 0);
+        assert_eq!(simulator.structure_simulator.get_visited_voxel_count(), 0);
+
+        // a hot voxel keeps its chunk simulated, the heat pass looks at it and the chunks around it
+        world.set_temperature(300.0, 3, CHUNK_SIZE + 3, 3);
+        world.wake(3, CHUNK_SIZE + 3, 3);
+        for _ in 0..5 {
+            simulator.next_step(&mut world, &mut ());
+            assert_eq!(simulator.heat_simulator.get_visited_voxel_count(), CHUNK_SIZE*CHUNK_SIZE*CHUNK_SIZE*3);
+            assert!(world.get_scheduled_chunks().contains(&(0, 1, 0)));
+        }
+        assert_eq!(simulator.structure_simulator.get_visited_voxel_count(), 0);
+    }
+
+    /// Average offset of the material's centre of mass from the centre of the floor, over several seeds. The material
+    /// starts as a `pool`x12x`pool` column in the middle of a `width`x16x`width` world.
+    fn average_drift(material: VoxelMaterial, width: usize, pool: usize, seeds: u64) -> (f64, f64) {
+        let start = (width - pool) / 2;
+        let centre = (width / 2) as f64;
+        let (mut drift_x, mut drift_z) = (0.0, 0.0);
+        for seed in 0..seeds {
+            let mut world = World::new(width, 16, width);
+            for y in 4..16 {
+                for z in start..start + pool {
+                    for x in start..start + pool {
+                        world.place(material, x, y, z);
+                    }
+                }
+            }
+            let mut simulator = VoxelSimulator::with_seed(seed);
+            for _ in 0..40 {
+                simulator.next_step(&mut world, &mut ());
+            }
+
+            let occupied = world.get_occupied();
+            drift_x += occupied.iter().map(|(x, _, _)| *x as f64 - centre).sum::<f64>() / occupied.len() as f64;
+            drift_z += occupied.iter().map(|(_, _, z)| *z as f64 - centre).sum::<f64>() / occupied.len() as f64;
+        }
+
+        (drift_x / seeds as f64, drift_z / seeds as f64)
+    }
+
+    #[test]
+    fn test_sand_pile_stays_symmetric() {
+        let (drift_x, drift_z) = average_drift(VoxelMaterial::from_name("Sand").unwrap(), 31, 5, 8);
+        assert!(drift_x.abs() < 0.25 && drift_z.abs() < 0.25, "sand drifted by {} {}", drift_x, drift_z);
+    }
+
+    #[test]
+    fn test_water_pool_stays_symmetric() {
+        // a spreading pool wanders much more than a pile, so it is kept small and averaged over many more seeds
+        let (drift_x, drift_z) = average_drift(VoxelMaterial::from_name("Water").unwrap(), 15, 3, 64);
+        assert!(drift_x.abs() < 0.3 && drift_z.abs() < 0.3, "water drifted by {} {}", drift_x, drift_z);
+    }
+
+    /// Pours 60 grains of a powder one by one onto the middle of a `width`x16x1 world, optionally filled with water
+    /// up to `water_level`, and lets the pile settle.
+    fn pour_pile(material: VoxelMaterial, width: usize, water_level: usize) -> World {
+        let height = 16;
+        let water = VoxelMaterial::from_name("Water").unwrap();
+        let mut world = World::new(width, height, 1);
+        for y in 0..water_level {
+            for x in 0..width {
+                world.place(water, x, y, 0);
+            }
+        }
+        let mut simulator = VoxelSimulator::with_seed(1);
+        for _ in 0..60 {
+            world.place(material, width / 2, height - 1, 0);
+            for _ in 0..3 {
+                simulator.next_step(&mut world, &mut ());
+            }
+        }
+        for _ in 0..300 {
+            simulator.next_step(&mut world, &mut ());
+        }
+
+        world
+    }
+
+    /// Height of a pile over half its base width.
+    fn get_slope(world: &World, material: VoxelMaterial) -> f64 {
+        let columns: Vec<usize> = (0..world.get_width())
+            .map(|x| (0..world.get_height()).filter(|y| world.get(x, *y, 0) == material).count())
+            .collect();
+        let pile_height = *columns.iter().max().unwrap() as f64;
+        let base = (columns.iter().rposition(|i| *i > 0).unwrap() - columns.iter().position(|i| *i > 0).unwrap() + 1) as f64;
+        pile_height / (base / 2.0)
+    }
+
+    #[test]
+    fn test_repose_angles() {
+        let mut slopes = vec![];
+        for material in ["Sand", "Salt", "Stone"].map(|i| VoxelMaterial::from_name(i).unwrap()) {
+            let world = pour_pile(material, 41, 0);
+            // grains that could never slide don't keep a settled pile awake
+            assert!(world.get_active_chunks().is_empty(), "{}", material);
+            slopes.push(get_slope(&world, material));
+        }
+        // 30, 45 and 65 degrees round to steps of 1 in 2, 1 in 1 and 2 in 1
+        assert!(slopes[0] < slopes[1] && slopes[1] < slopes[2], "{:?}", slopes);
+    }
+
+    #[test]
+    fn test_wet_sand_piles_steeper() {
+        let sand = VoxelMaterial::from_name("Sand").unwrap();
+        let dry = get_slope(&pour_pile(sand, 41, 0), sand);
+        let wet = get_slope(&pour_pile(sand, 21, 14), sand);
+        assert!(wet > dry * 2.0, "dry {} wet {}", dry, wet);
+    }
+
+    /// How far a column of liquid dropped in the middle of a flat 41x8x1 world spreads sideways in a few steps.
+    fn spread_width(material: VoxelMaterial) -> usize {
+        let mut world = World::new(41, 8, 1);
+        for y in 0..8 {
+            world.place(material, 20, y, 0);
+        }
+        let mut simulator = VoxelSimulator::with_seed(1);
+        for _ in 0..10 {
+            simulator.next_step(&mut world, &mut ());
+        }
+
+        let columns: Vec<usize> = (0..41).filter(|x| (0..8).any(|y| world.get(*x, y, 0) == material)).collect();
+        columns.last().unwrap() - columns.first().unwrap() + 1
+    }
+
+    #[test]
+    fn test_viscous_liquids_spread_slower() {
+        let water = spread_width(VoxelMaterial::from_name("Water").unwrap());
+        let oil = spread_width(VoxelMaterial::from_name("Oil").unwrap());
+        let lava = spread_width(VoxelMaterial::from_name("Lava").unwrap());
+        assert!(water > oil && oil > lava, "water {} oil {} lava {}", water, oil, lava);
+    }
+
+    #[test]
+    fn test_u_tube_levels_out() {
+        // metal front and back plates hold up the wall between the arms, which are joined by a channel at the bottom
+        let metal = VoxelMaterial::from_name("Metal").unwrap();
+        let water = VoxelMaterial::from_name("Water").unwrap();
+        let mut world = World::new(13, 16, 3);
+        for y in 0..16 {
+            for x in 0..13 {
+                world.place(metal, x, y, 0);
+                world.place(metal, x, y, 2);
+                if y == 0 || x == 0 || x == 12 || (y > 1 && (3..10).contains(&x)) {
+                    world.place(metal, x, y, 1);
+                }
+            }
+        }
+        for y in 1..14 {
+            for x in 1..3 {
+                world.place(water, x, y, 1);
+            }
+        }
+
+        let mut simulator = VoxelSimulator::with_seed(1);
+        for _ in 0..300 {
+            simulator.next_step(&mut world, &mut ());
+        }
+
+        let level = |arm: std::ops::Range<usize>| (0..16).rev()
+            .find(|y| arm.clone().any(|x| world.get(x, *y, 1) == water))
+            .unwrap();
+        let (left, right) = (level(1..3), level(10..12));
+        assert!(left.abs_diff(right) <= 1, "left arm at {}, right arm at {}", left, right);
+    }
+
+    #[test]
+    fn test_falling_powder_splashes_liquid() {
+        let water = VoxelMaterial::from_name("Water").unwrap();
+        let mut world = World::new(9, 32, 1);
+        for y in 0..4 {
+            for x in 0..9 {
+                world.place(water, x, y, 0);
+            }
+        }
+        world.place(VoxelMaterial::from_name("Sand").unwrap(), 4, 30, 0);
+
+        let mut simulator = VoxelSimulator::with_seed(1);
+        let mut highest = 0;
+        for _ in 0..20 {
+            simulator.next_step(&mut world, &mut ());
+            highest = highest.max((0..32).filter(|y| (0..9).any(|x| world.get(x, *y, 0) == water)).max().unwrap());
+        }
+        // the surface is at 3 and one displaced voxel alone would only rise to 4
+        assert!(highest > 4, "water only reached {}", highest);
+    }
+
+    fn count(world: &World, material: VoxelMaterial) -> usize {
+        world.get_material_counts()
+            .into_iter()
+            .find(|(i, _)| i.get_id() == material.get_id())
+            .map(|(_, count)| count)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_lava_is_quenched_by_water() {
+        let mut world = World::new(5, 8, 5);
+        for y in 0..4 {
+            for z in 0..5 {
+                for x in 0..5 {
+                    world.place(VoxelMaterial::from_name("Water").unwrap(), x, y, z);
+                }
+            }
+        }
+        world.place(VoxelMaterial::from_name("Lava").unwrap(), 2, 0, 2);
+        let mut simulator = VoxelSimulator::with_seed(5);
+
+        for _ in 0..100 {
+            simulator.next_step(&mut world, &mut ());
+        }
+
+        assert_eq!(count(&world, VoxelMaterial::from_name("Lava").unwrap()), 0);
+        assert_eq!(count(&world, VoxelMaterial::from_name("Stone").unwrap()), 1);
+    }
+
+    #[test]
+    fn test_lava_boils_water() {
+        let mut world = World::new(5, 8, 5);
+        for z in 0..5 {
+            for x in 0..5 {
+                world.place(VoxelMaterial::from_name("Lava").unwrap(), x, 0, z);
+                world.place(VoxelMaterial::from_name("Water").unwrap(), x, 1, z);
+            }
+        }
+        let mut simulator = VoxelSimulator::with_seed(5);
+
+        for _ in 0..100 {
+            simulator.next_step(&mut world, &mut ());
+        }
+
+        assert!(count(&world, VoxelMaterial::from_name("Steam").unwrap()) > 0);
+        assert_eq!(count(&world, VoxelMaterial::from_name("Lava").unwrap()), 25);
+    }
+
+    #[test]
+    fn test_fire_burns_out() {
+        let fire = VoxelMaterial::from_name("Fire").unwrap();
+        let mut world = World::new(5, 5, 5);
+        for x in 0..5 {
+            world.place(fire, x, 0, 2);
+        }
+        let mut simulator = VoxelSimulator::with_seed(9);
+
+        for _ in 0..19 {
+            simulator.next_step(&mut world, &mut ());
+        }
+        assert_eq!(count(&world, fire), 5);
+        for _ in 0..21 {
+            simulator.next_step(&mut world, &mut ());
+        }
+        assert_eq!(count(&world, fire), 0);
+        assert_eq!(count(&world, VoxelMaterial::from_name("Smoke").unwrap()), 5);
+    }
+
+    /// Heats the middle of a 3x1x3 layer of fuel on the floor and returns the ticks until it burned out and its peak temperature.
+    fn burn_fuel(fuel: VoxelMaterial) -> (usize, f32) {
+        let mut world = World::new(7, 12, 7);
+        for z in 2..5 {
+            for x in 2..5 {
+                world.place(fuel, x, 0, z);
+            }
+        }
+        world.set_temperature(600.0, 3, 0, 3);
+        let mut simulator = VoxelSimulator::with_seed(4);
+
+        let mut peak = 0.0f32;
+        for tick in 1..=2000 {
+            simulator.next_step(&mut world, &mut ());
+            for z in 0..7 {
+                for x in 0..7 {
+                    if world.get(x, 0, z) == fuel {
+                        peak = peak.max(world.get_temperature(x, 0, z));
+                    }
+                }
+            }
+            if count(&world, fuel) == 0 {
+                return (tick, peak);
+            }
+        }
+        panic!("{} did not burn out", fuel);
+    }
+
+    #[test]
+    fn test_wood_burns_to_ash_and_smoke() {
+        let wood = VoxelMaterial::from_name("Wood").unwrap();
+        let mut world = World::new(7, 12, 7);
+        for z in 2..5 {
+            for x in 2..5 {
+                world.place(wood, x, 0, z);
+            }
+        }
+        world.set_temperature(600.0, 3, 0, 3);
+        let mut simulator = VoxelSimulator::with_seed(4);
+
+        let mut smoke = 0;
+        for _ in 0..400 {
+            simulator.next_step(&mut world, &mut ());
+            smoke = smoke.max(count(&world, VoxelMaterial::from_name("Smoke").unwrap()));
+        }
+
+        assert_eq!(count(&world, wood), 0);
+        assert_eq!(count(&world, VoxelMaterial::from_name("Ash").unwrap()), 9);
+        assert!(smoke > 0);
+    }
+
+    #[test]
+    fn test_oil_burns_faster_and_hotter_than_wood() {
+        let (wood_ticks, wood_peak) = burn_fuel(VoxelMaterial::from_name("Wood").unwrap());
+        let (oil_ticks, oil_peak) = burn_fuel(VoxelMaterial::from_name("Oil").unwrap());
+        assert!(oil_ticks < wood_ticks, "{} {}", oil_ticks, wood_ticks);
+        assert!(oil_peak > wood_peak, "{} {}", oil_peak, wood_peak);
+    }
+
+    fn create_reactions() -> &'static MaterialReactions {
+        let source = r#"[
+            (reactant: "Metal", other: "Wood", products: ["Stone"], probability: 1.0, conditions: (min_count: 2)),
+            (reactant: "Ice", other: "Air", products: ["Ice", "Water"], probability: 1.0, conditions: (catalyst: Some("Metal"))),
+            (reactant: "Wood", other: "Ice", third: Some("Metal"), products: ["Stone", "Air", "Sand"], probability: 1.0),
+            (reactant: "Salt", other: "Metal", products: ["Salt", "Metal"], probability: 1.0, spawn: Some("Steam")),
+        ]"#;
+        Box::leak(Box::new(MaterialReactions::parse(source, MaterialRegistry::get()).unwrap()))
+    }
+
+    fn step_with_reactions(world: &mut World) {
+        let mut simulator = VoxelSimulator::with_seed(3);
+        simulator.set_reactions(create_reactions());
+        simulator.next_step(world, &mut ());
+    }
+
+    #[test]
+    fn test_reaction_min_count() {
+        let metal = VoxelMaterial::from_name("Metal").unwrap();
+        let wood = VoxelMaterial::from_name("Wood").unwrap();
+        let mut world = World::new(7, 1, 1);
+        for (x, material) in [wood, metal, wood, VoxelMaterial::AIR, VoxelMaterial::AIR, wood, metal].into_iter().enumerate() {
+            world.set(material, x, 0, 0);
+        }
+
+        step_with_reactions(&mut world);
+
+        assert_eq!(world.get(1, 0, 0), VoxelMaterial::from_name("Stone").unwrap());
+        assert_eq!(count(&world, wood), 2);
+        assert_eq!(world.get(6, 0, 0), metal);
+    }
+
+    #[test]
+    fn test_reaction_catalyst() {
+        let ice = VoxelMaterial::from_name("Ice").unwrap();
+        let metal = VoxelMaterial::from_name("Metal").unwrap();
+        let mut world = World::new(7, 1, 1);
+        world.set(metal, 0, 0, 0);
+        world.place(ice, 1, 0, 0);
+        world.place(ice, 5, 0, 0);
+
+        step_with_reactions(&mut world);
+
+        assert_eq!(world.get(2, 0, 0), VoxelMaterial::from_name("Water").unwrap());
+        assert_eq!(count(&world, VoxelMaterial::from_name("Water").unwrap()), 1);
+    }
+
+    #[test]
+    fn test_three_input_reaction() {
+        let mut world = World::new(3, 1, 1);
+        world.place(VoxelMaterial::from_name("Ice").unwrap(), 0, 0, 0);
+        world.set(VoxelMaterial::from_name("Wood").unwrap(), 1, 0, 0);
+        world.set(VoxelMaterial::from_name("Metal").unwrap(), 2, 0, 0);
+
+        step_with_reactions(&mut world);
+
+        assert_eq!(world.get(0, 0, 0), VoxelMaterial::AIR);
+        assert_eq!(world.get(1, 0, 0), VoxelMaterial::from_name("Stone").unwrap());
+        assert_eq!(world.get(2, 0, 0), VoxelMaterial::from_name("Sand").unwrap());
+    }
+
+    #[test]
+    fn test_reaction_spawn() {
+        let salt = VoxelMaterial::from_name("Salt").unwrap();
+        let metal = VoxelMaterial::from_name("Metal").unwrap();
+        let mut world = World::new(3, 1, 1);
+        world.set(metal, 0, 0, 0);
+        world.set(salt, 1, 0, 0);
+
+        step_with_reactions(&mut world);
+
+        assert_eq!(world.get(0, 0, 0), metal);
+        assert_eq!(world.get(1, 0, 0), salt);
+        assert_eq!(world.get(2, 0, 0), VoxelMaterial::from_name("Steam").unwrap());
+        assert_eq!(world.get_temperature(2, 0, 0), 110.0);
+    }
+}
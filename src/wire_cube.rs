@@ -12,15 +12,16 @@ pub struct WireCube {
     color: Point3<f32>,
 }
 impl WireCube {
-    pub fn new(x: f32, y: f32, z: f32, size: f32, color_r: f32, color_g: f32, color_b: f32) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(x: f32, y: f32, z: f32, size_x: f32, size_y: f32, size_z: f32, color_r: f32, color_g: f32, color_b: f32) -> Self {
         let a = Point3::new(x, y, z);
-        let b = Point3::new(x + size, y, z);
-        let c = Point3::new(x, y, z + size);
-        let d = Point3::new(x + size, y, z + size);
-        let e = Point3::new(x, y + size, z);
-        let f = Point3::new(x + size, y + size, z);
-        let g = Point3::new(x, y + size, z + size);
-        let h = Point3::new(x + size, y + size, z + size);
+        let b = Point3::new(x + size_x, y, z);
+        let c = Point3::new(x, y, z + size_z);
+        let d = Point3::new(x + size_x, y, z + size_z);
+        let e = Point3::new(x, y + size_y, z);
+        let f = Point3::new(x + size_x, y + size_y, z);
+        let g = Point3::new(x, y + size_y, z + size_z);
+        let h = Point3::new(x + size_x, y + size_y, z + size_z);
         let color = Point3::new(color_r, color_g, color_b);
         
         Self { a, b, c, d, e, f, g, h, color }
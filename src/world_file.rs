@@ -0,0 +1,355 @@
+//! Versioned binary world format, all integers little-endian:
+//!
+//! | field          | type                    | notes                                   |
+//! |----------------|-------------------------|-----------------------------------------|
+//! | magic          | `[u8; 4]`               | `b"SND3"`                               |
+//! | version        | `u16`                   | currently `4`                           |
+//! | width          | `u32`                   |                                         |
+//! | height         | `u32`                   |                                         |
+//! | depth          | `u32`                   |                                         |
+//! | seed           | `u64`                   | simulator seed                          |
+//! | tick           | `u64`                   | simulator step counter                  |
+//! | material count | `u16`                   |                                         |
+//! | materials      | `(u8, u8, [u8])` * n    | file id, name length, UTF-8 name        |
+//! | payload        | `(u32, u8)` * runs      | run length, file id                     |
+//! | temperatures   | `f32` * non-air voxels  | since version 2                         |
+//! | velocities     | `f32` * non-air voxels  | since version 3                         |
+//! | ages           | `u16` * non-air voxels  | since version 4                         |
+//!
+//! The payload run-length encodes every voxel in `World::coordiantes_to_index` order,
+//! temperatures, velocities and then ages follow for every non-air voxel in the same order.
+//! Version 1 files have no temperatures, their voxels start at the material's initial temperature.
+//! Voxels of files older than version 3 start at rest, those older than version 4 start at age 0.
+//! Materials are matched by name on load, so ids may change between versions of the game.
+
+use std::{fmt::Display, fs::File, io::{self, BufReader, BufWriter, Read, Write}, path::Path};
+
+use crate::model::{VoxelMaterial, World, MAX_WORLD_SIZE};
+
+const MAGIC: &[u8; 4] = b"SND3";
+const VERSION: u16 = 4;
+const MIN_VERSION: u16 = 1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SaveMetadata{
+    pub seed: u64,
+    pub tick: u64
+}
+
+#[derive(Debug)]
+pub enum WorldFileError {
+    Io(io::Error),
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    UnknownMaterial(String),
+    InvalidData(String),
+}
+impl Display for WorldFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::InvalidMagic => write!(f, "Not a world file"),
+            Self::UnsupportedVersion(version) => write!(f, "Unsupported world file version {}", version),
+            Self::UnknownMaterial(name) => write!(f, "Unknown material '{}'", name),
+            Self::InvalidData(message) => write!(f, "Invalid world file: {}", message),
+        }
+    }
+}
+impl From<io::Error> for WorldFileError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+fn read_u8(reader: &mut impl Read) -> Result<u8, WorldFileError> {
+    let mut buffer = [0; 1];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer[0])
+}
+
+fn read_u16(reader: &mut impl Read) -> Result<u16, WorldFileError> {
+    let mut buffer = [0; 2];
+    reader.read_exact(&mut buffer)?;
+    Ok(u16::from_le_bytes(buffer))
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, WorldFileError> {
+    let mut buffer = [0; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+fn read_f32(reader: &mut impl Read) -> Result<f32, WorldFileError> {
+    let mut buffer = [0; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(f32::from_le_bytes(buffer))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64, WorldFileError> {
+    let mut buffer = [0; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+fn write_run(writer: &mut impl Write, length: u32, material: VoxelMaterial) -> Result<(), WorldFileError> {
+    writer.write_all(&length.to_le_bytes())?;
+    writer.write_all(&[material.get_id() as u8])?;
+    Ok(())
+}
+
+pub fn save(world: &World, metadata: SaveMetadata, writer: &mut impl Write) -> Result<(), WorldFileError> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&(world.get_width() as u32).to_le_bytes())?;
+    writer.write_all(&(world.get_height() as u32).to_le_bytes())?;
+    writer.write_all(&(world.get_depth() as u32).to_le_bytes())?;
+    writer.write_all(&metadata.seed.to_le_bytes())?;
+    writer.write_all(&metadata.tick.to_le_bytes())?;
+
+    let materials = VoxelMaterial::get_all();
+    writer.write_all(&(materials.len() as u16).to_le_bytes())?;
+    for material in materials {
+        let name = material.to_string();
+        writer.write_all(&[material.get_id() as u8, name.len() as u8])?;
+        writer.write_all(name.as_bytes())?;
+    }
+
+    let mut current = VoxelMaterial::AIR;
+    let mut length = 0u32;
+    let mut temperatures = vec![];
+    let mut velocities = vec![];
+    let mut ages = vec![];
+    for y in 0..world.get_height() {
+        for z in 0..world.get_depth() {
+            for x in 0..world.get_width() {
+                let material = world.get(x, y, z);
+                if material != VoxelMaterial::AIR {
+                    temperatures.push(world.get_temperature(x, y, z));
+                    velocities.push(world.get_velocity(x, y, z));
+                    ages.push(world.get_age(x, y, z));
+                }
+                if material.get_id() == current.get_id() && length < u32::MAX {
+                    length += 1;
+                    continue;
+                }
+                if length > 0 {
+                    write_run(writer, length, current)?;
+                }
+                current = material;
+                length = 1;
+            }
+        }
+    }
+    write_run(writer, length, current)?;
+    for i in temperatures.into_iter().chain(velocities) {
+        writer.write_all(&i.to_le_bytes())?;
+    }
+    for i in ages {
+        writer.write_all(&i.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+pub fn load(reader: &mut impl Read) -> Result<(World, SaveMetadata), WorldFileError> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(WorldFileError::InvalidMagic);
+    }
+    let version = read_u16(reader)?;
+    if !(MIN_VERSION..=VERSION).contains(&version) {
+        return Err(WorldFileError::UnsupportedVersion(version));
+    }
+
+    let width = read_u32(reader)? as usize;
+    let height = read_u32(reader)? as usize;
+    let depth = read_u32(reader)? as usize;
+    let valid = |size: usize| size > 0 && size <= MAX_WORLD_SIZE;
+    if !valid(width) || !valid(height) || !valid(depth) {
+        return Err(WorldFileError::InvalidData(format!("invalid dimensions {}x{}x{}", width, height, depth)));
+    }
+    let volume = width.checked_mul(height)
+        .and_then(|i| i.checked_mul(depth))
+        .ok_or(WorldFileError::InvalidData(format!("invalid dimensions {}x{}x{}", width, height, depth)))?;
+    let seed = read_u64(reader)?;
+    let tick = read_u64(reader)?;
+
+    let mut materials: [Option<VoxelMaterial>; 256] = [None; 256];
+    for _ in 0..read_u16(reader)? {
+        let id = read_u8(reader)?;
+        let mut name = vec![0; read_u8(reader)? as usize];
+        reader.read_exact(&mut name)?;
+        let name = String::from_utf8(name)
+            .map_err(|_| WorldFileError::InvalidData("material name is not UTF-8".to_string()))?;
+        let material = VoxelMaterial::from_name(&name)
+            .ok_or(WorldFileError::UnknownMaterial(name))?;
+        materials[id as usize] = Some(material);
+    }
+
+    let mut world = World::new(width, height, depth);
+    let mut index = 0;
+    let mut occupied = vec![];
+    while index < volume {
+        let length = read_u32(reader)? as usize;
+        let id = read_u8(reader)?;
+        let material = materials[id as usize]
+            .ok_or(WorldFileError::InvalidData(format!("undeclared material id {}", id)))?;
+        if length == 0 || index + length > volume {
+            return Err(WorldFileError::InvalidData("run exceeds world volume".to_string()));
+        }
+        if material != VoxelMaterial::AIR {
+            for i in index..index+length {
+                let (x, y, z) = world.index_to_coordinates(i);
+                world.place(material, x, y, z);
+                occupied.push((x, y, z));
+            }
+        }
+        index += length;
+    }
+    if version >= 2 {
+        for (x, y, z) in &occupied {
+            let temperature = read_f32(reader)?;
+            world.set_temperature(temperature, *x, *y, *z);
+        }
+    }
+    if version >= 3 {
+        for (x, y, z) in &occupied {
+            let velocity = read_f32(reader)?;
+            world.set_velocity(velocity, *x, *y, *z);
+        }
+    }
+    if version >= 4 {
+        for (x, y, z) in occupied {
+            let age = read_u16(reader)?;
+            world.set_age(age, x, y, z);
+        }
+    }
+
+    Ok((world, SaveMetadata { seed, tick }))
+}
+
+pub fn save_to_path(world: &World, metadata: SaveMetadata, path: &Path) -> Result<(), WorldFileError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    save(world, metadata, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn load_from_path(path: &Path) -> Result<(World, SaveMetadata), WorldFileError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    load(&mut reader)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{load, save, SaveMetadata, WorldFileError, MAGIC, VERSION};
+    use crate::model::{VoxelMaterial, World, MAX_WORLD_SIZE};
+
+    fn assert_same(first: &World, second: &World) {
+        assert_eq!(first.get_width(), second.get_width());
+        assert_eq!(first.get_height(), second.get_height());
+        assert_eq!(first.get_depth(), second.get_depth());
+        for y in 0..first.get_height() {
+            for z in 0..first.get_depth() {
+                for x in 0..first.get_width() {
+                    assert_eq!(first.get(x, y, z).get_id(), second.get(x, y, z).get_id());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut world = World::new(20, 7, 33);
+        for (i, material) in VoxelMaterial::get_all().into_iter().enumerate() {
+            world.set(material, i, 0, 0);
+            world.set(material, 19, 6, 32 - i);
+        }
+        for x in 0..20 {
+            world.set(VoxelMaterial::from_name("Sand").unwrap(), x, 3, 17);
+        }
+        world.set_temperature(-40.5, 3, 3, 17);
+        world.set_temperature(1234.0, 19, 6, 31);
+        world.set_velocity(-3.5, 3, 3, 17);
+        world.set_age(77, 19, 6, 31);
+        let metadata = SaveMetadata { seed: 42, tick: 1234 };
+
+        let mut buffer = vec![];
+        save(&world, metadata, &mut buffer).unwrap();
+        let (loaded, loaded_metadata) = load(&mut buffer.as_slice()).unwrap();
+
+        assert_eq!(metadata, loaded_metadata);
+        assert_same(&world, &loaded);
+        assert_eq!(loaded.get_temperature(3, 3, 17), -40.5);
+        assert_eq!(loaded.get_temperature(19, 6, 31), 1234.0);
+        assert_eq!(loaded.get_velocity(3, 3, 17), -3.5);
+        assert_eq!(loaded.get_velocity(4, 3, 17), 0.0);
+        assert_eq!(loaded.get_age(19, 6, 31), 77);
+        assert_eq!(loaded.get_age(3, 3, 17), 0);
+    }
+
+    #[test]
+    fn test_load_version_1() {
+        let mut buffer = MAGIC.to_vec();
+        buffer.extend(1u16.to_le_bytes());
+        for i in [2u32, 1, 1] {
+            buffer.extend(i.to_le_bytes());
+        }
+        buffer.extend(7u64.to_le_bytes());
+        buffer.extend(8u64.to_le_bytes());
+        buffer.extend(1u16.to_le_bytes());
+        buffer.extend([3, 4]);
+        buffer.extend(b"Lava");
+        buffer.extend(2u32.to_le_bytes());
+        buffer.push(3);
+
+        let (world, metadata) = load(&mut buffer.as_slice()).unwrap();
+        assert_eq!(metadata, SaveMetadata { seed: 7, tick: 8 });
+        assert_eq!(world.get(1, 0, 0), VoxelMaterial::from_name("Lava").unwrap());
+        assert_eq!(world.get_temperature(1, 0, 0), 1200.0);
+    }
+
+    #[test]
+    fn test_empty_world_is_compact() {
+        let world = World::new(128, 64, 128);
+        let mut buffer = vec![];
+        save(&world, SaveMetadata::default(), &mut buffer).unwrap();
+        assert!(buffer.len() < 256);
+
+        let (loaded, _) = load(&mut buffer.as_slice()).unwrap();
+        assert_eq!(loaded.get_allocated_chunk_count(), 0);
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        assert!(matches!(load(&mut b"NOPE".as_slice()), Err(WorldFileError::InvalidMagic)));
+
+        let mut buffer = MAGIC.to_vec();
+        buffer.extend(99u16.to_le_bytes());
+        assert!(matches!(load(&mut buffer.as_slice()), Err(WorldFileError::UnsupportedVersion(99))));
+
+        let mut buffer = vec![];
+        save(&World::new(2, 2, 2), SaveMetadata::default(), &mut buffer).unwrap();
+        let truncated = &buffer[..buffer.len() - 3];
+        assert!(matches!(load(&mut &truncated[..]), Err(WorldFileError::Io(_))));
+
+        let mut renamed = buffer.clone();
+        let position = renamed.windows(4).position(|i| i == b"Sand").unwrap();
+        renamed[position..position+4].copy_from_slice(b"Mud!");
+        assert!(matches!(load(&mut renamed.as_slice()), Err(WorldFileError::UnknownMaterial(_))));
+    }
+
+    #[test]
+    fn test_malformed_header() {
+        for size in [(0, 1, 1), (MAX_WORLD_SIZE as u32 + 1, 1, 1), (u32::MAX, u32::MAX, u32::MAX)] {
+            let mut buffer = MAGIC.to_vec();
+            buffer.extend(VERSION.to_le_bytes());
+            for i in [size.0, size.1, size.2] {
+                buffer.extend(i.to_le_bytes());
+            }
+            buffer.extend([0; 16]);
+            assert!(matches!(load(&mut buffer.as_slice()), Err(WorldFileError::InvalidData(_))), "{:?}", size);
+        }
+    }
+}